@@ -0,0 +1,185 @@
+use crate::domain::*;
+use std::f64;
+
+// Differences below this are rounding noise rather than a better route.
+pub const EPSILON: f64 = 1e-12;
+
+// Cost of an edge in log space: the best rate is the cheapest route.
+pub fn cost_of(weight: f64) -> f64 {
+    -weight.ln()
+}
+
+// Best exchange rate between every pair of nodes, kept up to date edge by edge.
+// Rates are multiplied along a route, so they are stored as `-ln(rate)` costs
+// that add up instead; this keeps long chains of tiny or huge factors exact
+// enough and free of overflow. `pred[i][j]` is the node just before `j` on the
+// best route from `i`, so every row is a best-rate tree rooted at its source.
+#[derive(Debug, Clone)]
+pub struct AllPairs {
+    cost: Vec<Vec<f64>>,
+    pred: Vec<Vec<Option<usize>>>,
+}
+
+impl AllPairs {
+    // Full Floyd–Warshall, O(n³).
+    pub fn compute(node_count: usize, paths: &[Path]) -> AllPairs {
+        let mut all_pairs = AllPairs {
+            cost: Vec::with_capacity(node_count),
+            pred: Vec::with_capacity(node_count),
+        };
+        for _ in 0..node_count {
+            all_pairs.add_node();
+        }
+
+        for p in paths {
+            let from_index = p.start_node.index;
+            let to_index = p.end_node.index;
+            let cost = cost_of(p.weight);
+            if cost < all_pairs.cost[from_index][to_index] {
+                all_pairs.cost[from_index][to_index] = cost;
+                all_pairs.pred[from_index][to_index] = Some(from_index);
+            }
+        }
+
+        let cost = &mut all_pairs.cost;
+        let pred = &mut all_pairs.pred;
+        for k in 0..node_count {
+            for i in 0..node_count {
+                for j in 0..node_count {
+                    if cost[i][k] + cost[k][j] < cost[i][j] - EPSILON {
+                        cost[i][j] = cost[i][k] + cost[k][j];
+                        pred[i][j] = pred[k][j];
+                    }
+                }
+            }
+        }
+
+        all_pairs
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.cost.len()
+    }
+
+    // A node that can reach itself at a negative cost sits on a profitable cycle.
+    pub fn has_negative_cycle(&self) -> bool {
+        (0..self.node_count()).any(|i| self.cost[i][i] < -EPSILON)
+    }
+
+    #[cfg(test)]
+    pub fn cost(&self, from: usize, to: usize) -> f64 {
+        self.cost[from][to]
+    }
+
+    // Node indices of the best route from `from` to `to`, both ends included.
+    // Returns `None` if `to` is unreachable or the predecessor tree is corrupt.
+    pub fn route(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        if self.cost[from][to] == f64::INFINITY {
+            return None;
+        }
+        let mut route = vec![to];
+        let mut current = to;
+        while current != from {
+            if route.len() > self.node_count() {
+                return None;
+            }
+            current = self.pred[from][current]?;
+            route.push(current);
+        }
+        route.reverse();
+        Some(route)
+    }
+
+    pub fn add_node(&mut self) {
+        let index = self.cost.len();
+        for row in self.cost.iter_mut() {
+            row.push(f64::INFINITY);
+        }
+        for row in self.pred.iter_mut() {
+            row.push(None);
+        }
+        let mut cost_row = vec![f64::INFINITY; index + 1];
+        cost_row[index] = 0.0;
+        self.cost.push(cost_row);
+        self.pred.push(vec![None; index + 1]);
+    }
+
+    // Propagates a new or improved edge through every pair in O(n²).
+    // Returns `false` when the edge closes a cycle whose product is above 1,
+    // in which case the matrices are no longer meaningful and must be rebuilt.
+    pub fn insert_edge(&mut self, from: usize, to: usize, weight: f64) -> bool {
+        let edge_cost = cost_of(weight);
+        if self.cost[to][from] + edge_cost < -EPSILON {
+            return false;
+        }
+
+        let n = self.node_count();
+        let (cost, pred) = (&mut self.cost, &mut self.pred);
+        for i in 0..n {
+            let into_edge = cost[i][from] + edge_cost;
+            if into_edge == f64::INFINITY {
+                continue;
+            }
+            for j in 0..n {
+                let candidate = into_edge + cost[to][j];
+                if candidate < cost[i][j] - EPSILON {
+                    cost[i][j] = candidate;
+                    pred[i][j] = if j == to { Some(from) } else { pred[to][j] };
+                }
+            }
+        }
+        true
+    }
+
+    // Applies a weight change of an existing edge. `paths` must already hold the
+    // new weight. Improvements are propagated like an insertion; for a worse
+    // weight only the rows whose best-rate tree uses the edge are recomputed.
+    pub fn update_edge(
+        &mut self,
+        from: usize,
+        to: usize,
+        old_weight: f64,
+        new_weight: f64,
+        paths: &[Path],
+    ) -> bool {
+        if new_weight >= old_weight {
+            return self.insert_edge(from, to, new_weight);
+        }
+
+        for source in 0..self.node_count() {
+            if self.pred[source][to] == Some(from) {
+                self.recompute_row(source, paths);
+            }
+        }
+        true
+    }
+
+    // Single-source Bellman–Ford from `source`, O(n·m).
+    fn recompute_row(&mut self, source: usize, paths: &[Path]) {
+        let n = self.node_count();
+        let cost = &mut self.cost[source];
+        let pred = &mut self.pred[source];
+        for j in 0..n {
+            cost[j] = f64::INFINITY;
+            pred[j] = None;
+        }
+        cost[source] = 0.0;
+
+        for _ in 1..n {
+            let mut changed = false;
+            for p in paths {
+                let from_index = p.start_node.index;
+                let to_index = p.end_node.index;
+                let candidate = cost[from_index] + cost_of(p.weight);
+                if candidate < cost[to_index] - EPSILON {
+                    cost[to_index] = candidate;
+                    pred[to_index] = Some(from_index);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::all_pairs::*;
+use crate::domain::*;
+use std::collections::HashSet;
+
+// Cycles whose rate product is above 1, found with Bellman–Ford on `-ln(weight)`
+// costs: a profitable cycle is a negative cycle in log space. Every node is a
+// source at once (as if linked from a virtual node at cost 0), so cycles in any
+// component are found. Each cycle is returned as the indices into `paths` of its
+// edges, in trading order, starting from its lowest node index. Only the paths
+// whose index `usable` holds for take part.
+//
+// Only the distinct cycles visible in the final predecessor graph are reported;
+// overlapping cycles that share a node with a better one may be hidden by it.
+pub fn find_cycles<F>(node_count: usize, paths: &[Path], usable: F) -> Vec<Vec<usize>>
+where
+    F: Fn(usize) -> bool,
+{
+    let mut cost = vec![0.0; node_count];
+    let mut pred: Vec<Option<usize>> = vec![None; node_count];
+    let mut relaxed = vec![];
+
+    for _ in 0..node_count {
+        relaxed.clear();
+        for (i, p) in paths.iter().enumerate().filter(|&(i, _)| usable(i)) {
+            let candidate = cost[p.start_node.index] + cost_of(p.weight);
+            if candidate < cost[p.end_node.index] - EPSILON {
+                cost[p.end_node.index] = candidate;
+                pred[p.end_node.index] = Some(i);
+                relaxed.push(p.end_node.index);
+            }
+        }
+        if relaxed.is_empty() {
+            return vec![];
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut cycles = vec![];
+    for &node in relaxed.iter() {
+        // n steps back from a node still relaxed in the n-th pass lands on a cycle
+        let mut on_cycle = Some(node);
+        for _ in 0..node_count {
+            on_cycle = on_cycle
+                .and_then(|n| pred[n])
+                .map(|p| paths[p].start_node.index);
+        }
+        let start = match on_cycle {
+            Some(n) => n,
+            None => continue,
+        };
+
+        let mut cycle = vec![];
+        let mut current = start;
+        while let Some(p) = pred[current] {
+            cycle.push(p);
+            current = paths[p].start_node.index;
+            if current == start || cycle.len() > node_count {
+                break;
+            }
+        }
+        if current != start {
+            continue;
+        }
+
+        cycle.reverse();
+        let lowest = (0..cycle.len())
+            .min_by_key(|&i| paths[cycle[i]].start_node.index)
+            .unwrap_or(0);
+        cycle.rotate_left(lowest);
+        if seen.insert(cycle.clone()) {
+            cycles.push(cycle);
+        }
+    }
+    cycles
+}
+
+// Upper bound on the edges a simple-path search looks at before it settles for
+// the best routes found so far.
+const MAX_EXPANSIONS: usize = 1_000_000;
+
+// Best route from `from` to `to` that visits every node at most once, restricted
+// to the nodes marked in `usable`, to the paths marked in `usable_paths` and to
+// at most `max_hops` edges. Once a profitable cycle is reachable the best rate is
+// unbounded and the shortest-path algorithms no longer apply, so this is an
+// exhaustive depth-first search: exponential in the worst case, which is why it
+// is capped by `MAX_EXPANSIONS`.
+pub fn best_simple_path(
+    paths: &[Path],
+    adjacency: &[Vec<usize>],
+    usable: &[bool],
+    usable_paths: &[bool],
+    from: usize,
+    to: usize,
+    max_hops: Option<usize>,
+) -> Option<Route> {
+    let visited = usable.iter().map(|u| !u).collect();
+    let mut search = SimplePathSearch::new(paths, adjacency, usable_paths, visited, Some(to));
+    search.max_hops = max_hops;
+    search.run(from).swap_remove(to)
+}
+
+// Best routes from `from` to every node, each visiting every node at most once
+// and taking the paths marked in `usable_paths`, or `None` where there's none.
+// One search serves all destinations, under the same cap as `best_simple_path`.
+pub fn best_simple_paths_from(
+    paths: &[Path],
+    adjacency: &[Vec<usize>],
+    usable_paths: &[bool],
+    from: usize,
+) -> Vec<Option<Route>> {
+    let visited = vec![false; adjacency.len()];
+    SimplePathSearch::new(paths, adjacency, usable_paths, visited, None).run(from)
+}
+
+// A route as node indices, both ends included. `approximate` when the search
+// stopped at `MAX_EXPANSIONS`, so a better route may have gone unseen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub nodes: Vec<usize>,
+    pub approximate: bool,
+}
+
+impl Route {
+    pub fn exact(nodes: Vec<usize>) -> Self {
+        Route {
+            nodes,
+            approximate: false,
+        }
+    }
+}
+
+struct SimplePathSearch<'a> {
+    paths: &'a [Path],
+    adjacency: &'a [Vec<usize>],
+    usable_paths: &'a [bool],
+    max_hops: Option<usize>,
+    // the destination, or `None` to keep the best route to every node
+    to: Option<usize>,
+    visited: Vec<bool>,
+    route: Vec<usize>,
+    best: Vec<Option<(f64, Vec<usize>)>>,
+    expansions: usize,
+    capped: bool,
+}
+
+impl<'a> SimplePathSearch<'a> {
+    fn new(
+        paths: &'a [Path],
+        adjacency: &'a [Vec<usize>],
+        usable_paths: &'a [bool],
+        visited: Vec<bool>,
+        to: Option<usize>,
+    ) -> Self {
+        SimplePathSearch {
+            paths,
+            adjacency,
+            usable_paths,
+            max_hops: None,
+            to,
+            best: vec![None; visited.len()],
+            visited,
+            route: vec![],
+            expansions: 0,
+            capped: false,
+        }
+    }
+
+    // The best route found to each node.
+    fn run(mut self, from: usize) -> Vec<Option<Route>> {
+        self.visited[from] = true;
+        self.route.push(from);
+        self.visit(from, 0.0);
+        let approximate = self.capped;
+        self.best
+            .into_iter()
+            .map(|b| b.map(|(_, nodes)| Route { nodes, approximate }))
+            .collect()
+    }
+
+    fn visit(&mut self, node: usize, cost: f64) {
+        if self.to.is_none_or(|to| to == node)
+            && self.best[node]
+                .as_ref()
+                .is_none_or(|b| cost < b.0 - EPSILON)
+        {
+            self.best[node] = Some((cost, self.route.clone()));
+        }
+        if self.to == Some(node) || self.max_hops.is_some_and(|h| self.route.len() > h) {
+            return;
+        }
+
+        let adjacency = self.adjacency;
+        for &p in adjacency[node].iter() {
+            if self.expansions >= MAX_EXPANSIONS {
+                self.capped = true;
+                return;
+            }
+            self.expansions += 1;
+
+            let path = &self.paths[p];
+            let next = path.end_node.index;
+            if self.visited[next] || !self.usable_paths[p] {
+                continue;
+            }
+            self.visited[next] = true;
+            self.route.push(next);
+            self.visit(next, cost + cost_of(path.weight));
+            self.route.pop();
+            self.visited[next] = false;
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use crate::error::*;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// Largest scale accepted by `:decimal`; keeps 10^scale well inside an i128.
+pub const MAX_SCALE: u32 = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    HalfUp,
+    HalfEven,
+    Down,
+    Up,
+}
+
+impl FromStr for Rounding {
+    type Err = DecimalModeValidationError;
+
+    fn from_str(s: &str) -> Result<Rounding, DecimalModeValidationError> {
+        match s.trim().to_lowercase().as_str() {
+            "half-up" => Ok(Rounding::HalfUp),
+            "half-even" => Ok(Rounding::HalfEven),
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            _ => Err(DecimalModeValidationError::UnknownRounding),
+        }
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Rounding::HalfUp => "half-up",
+            Rounding::HalfEven => "half-even",
+            Rounding::Down => "down",
+            Rounding::Up => "up",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// How exact rates are rounded before they are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecimalMode {
+    pub scale: u32,
+    pub rounding: Rounding,
+}
+
+impl DecimalMode {
+    pub fn new(scale: u32, rounding: Rounding) -> Self {
+        DecimalMode { scale, rounding }
+    }
+}
+
+// Fixed-point decimal number `mantissa * 10^-scale`. Multiplication is exact;
+// anything that does not fit in an i128 mantissa is reported instead of rounded.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn zero() -> Decimal {
+        Decimal {
+            mantissa: 0,
+            scale: 0,
+        }
+    }
+
+    pub fn one() -> Decimal {
+        Decimal {
+            mantissa: 1,
+            scale: 0,
+        }
+    }
+
+    // Exact value of the shortest decimal string that round-trips to `value`.
+    pub fn from_f64(value: f64) -> Option<Decimal> {
+        value.to_string().parse().ok()
+    }
+
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        Some(
+            Decimal {
+                mantissa: self.mantissa.checked_mul(other.mantissa)?,
+                scale: self.scale.checked_add(other.scale)?,
+            }
+            .normalized(),
+        )
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (
+            self.round(scale, Rounding::Down)?,
+            other.round(scale, Rounding::Down)?,
+        );
+        Some(
+            Decimal {
+                mantissa: a.mantissa.checked_sub(b.mantissa)?,
+                scale,
+            }
+            .normalized(),
+        )
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa < 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.mantissa > 0
+    }
+
+    // Nearest f64, for the log-space routing.
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    // Rounds (or pads) to exactly `scale` fractional digits.
+    pub fn round(self, scale: u32, rounding: Rounding) -> Option<Decimal> {
+        if self.scale <= scale {
+            let factor = 10_i128.checked_pow(scale - self.scale)?;
+            return Some(Decimal {
+                mantissa: self.mantissa.checked_mul(factor)?,
+                scale,
+            });
+        }
+
+        let magnitude = self.mantissa.unsigned_abs();
+        let (quotient, round_away) = match 10_u128.checked_pow(self.scale - scale) {
+            Some(divisor) => {
+                let (quotient, remainder) = (magnitude / divisor, magnitude % divisor);
+                let round_away = match rounding {
+                    Rounding::Down => false,
+                    Rounding::Up => remainder > 0,
+                    Rounding::HalfUp => remainder * 2 >= divisor,
+                    Rounding::HalfEven => {
+                        remainder * 2 > divisor || (remainder * 2 == divisor && quotient % 2 == 1)
+                    }
+                };
+                (quotient, round_away)
+            }
+            // the divisor is larger than any mantissa, so only `Up` moves away from zero
+            None => (0, rounding == Rounding::Up && magnitude > 0),
+        };
+
+        let magnitude = quotient + if round_away { 1 } else { 0 };
+        let mantissa = if self.mantissa < 0 {
+            -(magnitude as i128)
+        } else {
+            magnitude as i128
+        };
+        Some(Decimal { mantissa, scale })
+    }
+
+    fn normalized(mut self) -> Decimal {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+}
+
+// Exact product of any number of decimals, for the rate of a route: each hop
+// adds digits to the mantissa, which soon outgrows an i128, so it is kept in
+// base 10^9 limbs and only rounded once, at the end.
+#[derive(Debug, Clone)]
+pub struct ExactProduct {
+    negative: bool,
+    // least significant first
+    limbs: Vec<u32>,
+    scale: u32,
+}
+
+const LIMB_BASE: u64 = 1_000_000_000;
+
+impl ExactProduct {
+    pub fn one() -> ExactProduct {
+        ExactProduct {
+            negative: false,
+            limbs: vec![1],
+            scale: 0,
+        }
+    }
+
+    pub fn times(mut self, factor: Decimal) -> ExactProduct {
+        let mut magnitude = factor.mantissa.unsigned_abs();
+        let mut other = vec![];
+        while magnitude > 0 {
+            other.push((magnitude % u128::from(LIMB_BASE)) as u32);
+            magnitude /= u128::from(LIMB_BASE);
+        }
+        let mut limbs = vec![0_u32; self.limbs.len() + other.len() + 1];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0_u64;
+            for (j, &b) in other.iter().enumerate() {
+                let t = u64::from(limbs[i + j]) + u64::from(a) * u64::from(b) + carry;
+                limbs[i + j] = (t % LIMB_BASE) as u32;
+                carry = t / LIMB_BASE;
+            }
+            limbs[i + other.len()] = carry as u32;
+        }
+        self.limbs = limbs;
+        self.trim();
+        self.negative ^= factor.mantissa < 0;
+        self.scale += factor.scale;
+        self
+    }
+
+    // Rounds to exactly `scale` fractional digits; `None` when the result
+    // doesn't fit a `Decimal`.
+    pub fn round(mut self, scale: u32, rounding: Rounding) -> Option<Decimal> {
+        while self.scale < scale {
+            self.multiply_small(10);
+            self.scale += 1;
+        }
+        // the most significant dropped digit, and whether any below it is set
+        let (mut digit, mut sticky) = (0, false);
+        while self.scale > scale {
+            sticky |= digit != 0;
+            digit = self.divide_small(10);
+            self.scale -= 1;
+        }
+        let odd = self.limbs[0] % 2 == 1;
+        let round_away = match rounding {
+            Rounding::Down => false,
+            Rounding::Up => digit > 0 || sticky,
+            Rounding::HalfUp => digit >= 5,
+            Rounding::HalfEven => digit > 5 || (digit == 5 && (sticky || odd)),
+        };
+        if round_away {
+            self.add_small(1);
+        }
+
+        let mut mantissa: i128 = 0;
+        for &limb in self.limbs.iter().rev() {
+            mantissa = mantissa
+                .checked_mul(LIMB_BASE as i128)?
+                .checked_add(i128::from(limb))?;
+        }
+        if self.negative {
+            mantissa = -mantissa;
+        }
+        Some(Decimal { mantissa, scale })
+    }
+
+    fn multiply_small(&mut self, m: u32) {
+        let mut carry = 0_u64;
+        for limb in self.limbs.iter_mut() {
+            let t = u64::from(*limb) * u64::from(m) + carry;
+            *limb = (t % LIMB_BASE) as u32;
+            carry = t / LIMB_BASE;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    // Returns the remainder.
+    fn divide_small(&mut self, d: u32) -> u32 {
+        let mut remainder = 0_u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let t = remainder * LIMB_BASE + u64::from(*limb);
+            *limb = (t / u64::from(d)) as u32;
+            remainder = t % u64::from(d);
+        }
+        self.trim();
+        remainder as u32
+    }
+
+    fn add_small(&mut self, a: u32) {
+        let mut carry = u64::from(a);
+        for limb in self.limbs.iter_mut() {
+            let t = u64::from(*limb) + carry;
+            *limb = (t % LIMB_BASE) as u32;
+            carry = t / LIMB_BASE;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    // Drops leading zero limbs, keeping at least one.
+    fn trim(&mut self) {
+        while self.limbs.len() > 1 && self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        let (a, b) = (self.normalized(), other.normalized());
+        a.mantissa == b.mantissa && a.scale == b.scale
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = DecimalError;
+
+    fn from_str(s: &str) -> Result<Decimal, DecimalError> {
+        let s = s.trim();
+        let (negative, s) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (number, exponent) = match s.find(['e', 'E']) {
+            Some(i) => (
+                &s[..i],
+                s[i + 1..]
+                    .parse::<i32>()
+                    .map_err(|_| DecimalError::InvalidFormat)?,
+            ),
+            None => (s, 0),
+        };
+        let (integer, fraction) = match number.find('.') {
+            Some(i) => (&number[..i], &number[i + 1..]),
+            None => (number, ""),
+        };
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(DecimalError::InvalidFormat);
+        }
+
+        let mut mantissa: i128 = 0;
+        for c in integer.chars().chain(fraction.chars()) {
+            let digit = c.to_digit(10).ok_or(DecimalError::InvalidFormat)?;
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(i128::from(digit)))
+                .ok_or(DecimalError::Overflow)?;
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+
+        let scale = fraction.len() as i64 - i64::from(exponent);
+        if scale >= 0 {
+            Ok(Decimal {
+                mantissa,
+                scale: scale as u32,
+            })
+        } else {
+            let factor = u32::try_from(-scale)
+                .ok()
+                .and_then(|e| 10_i128.checked_pow(e))
+                .ok_or(DecimalError::Overflow)?;
+            Ok(Decimal {
+                mantissa: mantissa.checked_mul(factor).ok_or(DecimalError::Overflow)?,
+                scale: 0,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let digits = if digits.len() <= scale {
+            format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+        } else {
+            digits
+        };
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer)
+        } else {
+            write!(f, "{}{}.{}", sign, integer, fraction)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!("1000.0", d("1000.0").to_string());
+        assert_eq!("0.0009", d(" 0.0009 ").to_string());
+        assert_eq!(
+            "-12.5",
+            d("-12.50").round(1, Rounding::Down).unwrap().to_string()
+        );
+        assert_eq!("1000000", d("1e6").to_string());
+        assert_eq!("0.000001", d("1E-6").to_string());
+        assert_eq!(d("1000"), d("1000.000"));
+        assert_eq!(Some(d("0.1")), Decimal::from_f64(0.1));
+        assert_eq!(
+            Err(DecimalError::InvalidFormat),
+            "1abc00".parse::<Decimal>()
+        );
+        assert_eq!(Err(DecimalError::InvalidFormat), ".".parse::<Decimal>());
+        assert_eq!(Err(DecimalError::Overflow), "1e40".parse::<Decimal>());
+    }
+
+    #[test]
+    fn multiply_exactly() {
+        let product = d("0.1").checked_mul(d("0.1")).unwrap();
+        assert_eq!("0.01", product.to_string());
+        assert_eq!(
+            "0.9",
+            d("1000.0").checked_mul(d("0.0009")).unwrap().to_string()
+        );
+        assert_eq!(None, d("1e30").checked_mul(d("1e30")));
+    }
+
+    #[test]
+    fn multiply_any_number_of_factors_before_rounding() {
+        // a 57-digit mantissa in all, far beyond an i128 until rounded
+        let product = (0..4).fold(ExactProduct::one(), |p, _| {
+            p.times(d("123.45678901").checked_mul(d("0.9974")).unwrap())
+        });
+        assert_eq!(
+            "229899149.35435565",
+            product
+                .clone()
+                .round(8, Rounding::HalfEven)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "229899149.35435564",
+            product
+                .clone()
+                .round(8, Rounding::Down)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "-0.25",
+            ExactProduct::one()
+                .times(d("-0.5"))
+                .times(d("0.5"))
+                .round(2, Rounding::HalfUp)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "0.2",
+            ExactProduct::one()
+                .times(d("0.25"))
+                .round(1, Rounding::HalfEven)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            None,
+            ExactProduct::one()
+                .times(d("1e30"))
+                .times(d("1e30"))
+                .round(0, Rounding::Down)
+        );
+    }
+
+    #[test]
+    fn subtract_exactly() {
+        let difference = d("1").checked_sub(d("0.001")).unwrap();
+        assert_eq!("0.999", difference.to_string());
+        assert_eq!(0.999, difference.to_f64());
+        let difference = d("0.1").checked_sub(d("0.25")).unwrap();
+        assert_eq!("-0.15", difference.to_string());
+        assert!(difference.is_negative());
+        assert!(!d("0").is_positive());
+    }
+
+    #[test]
+    fn round_with_each_rule() {
+        let cases = [
+            ("2.345", Rounding::HalfUp, "2.35"),
+            ("2.345", Rounding::HalfEven, "2.34"),
+            ("2.355", Rounding::HalfEven, "2.36"),
+            ("2.349", Rounding::Down, "2.34"),
+            ("2.341", Rounding::Up, "2.35"),
+            ("-2.345", Rounding::HalfUp, "-2.35"),
+            ("7", Rounding::HalfUp, "7.00"),
+        ];
+        for &(value, rounding, expected) in cases.iter() {
+            assert_eq!(expected, d(value).round(2, rounding).unwrap().to_string());
+        }
+        assert_eq!(
+            "0.00",
+            d("1e-45")
+                .checked_mul(d("1"))
+                .unwrap()
+                .round(2, Rounding::HalfUp)
+                .unwrap()
+                .to_string()
+        );
+    }
+}
@@ -1,6 +1,7 @@
 extern crate chrono;
+use crate::decimal::*;
 use chrono::prelude::*;
-use std::cell::Cell;
+use chrono::Duration;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
@@ -8,15 +9,19 @@ use std::rc::Rc;
 pub struct Node {
     pub exchange: String,
     pub currency: String,
-    pub index: Cell<usize>,
+    // never reused, even after the node is removed
+    pub id: usize,
+    // position in the graph, renumbered when nodes are removed
+    pub index: usize,
 }
 
 impl Node {
-    pub fn new(exchange: &str, currency: &str, idx: usize) -> Self {
+    pub fn new(exchange: &str, currency: &str, id: usize, idx: usize) -> Self {
         Node {
             exchange: exchange.to_owned(),
             currency: currency.to_owned(),
-            index: Cell::new(idx),
+            id,
+            index: idx,
         }
     }
 }
@@ -41,16 +46,48 @@ pub enum Factor {
 pub struct Path {
     pub start_node: Rc<Node>,
     pub end_node: Rc<Node>,
-    pub weight: f32,
+    // net of trading fees; routes are chosen on it
+    pub weight: f64,
+    pub exact_weight: Option<Decimal>,
+    // the quote itself, before trading fees
+    pub gross_weight: f64,
+    pub exact_gross_weight: Option<Decimal>,
     pub timestamp: DateTime<Utc>,
     pub factor_type: Factor,
+    // every quote received for the path, oldest first
+    pub history: Vec<PathVersion>,
+    // halted pairs keep their quotes but aren't routed on
+    pub halted: bool,
+}
+
+// A quote of a path as it was received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathVersion {
+    pub timestamp: DateTime<Utc>,
+    pub gross_weight: f64,
+    pub exact_gross_weight: Option<Decimal>,
+}
+
+impl PathVersion {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        gross_weight: f64,
+        exact_gross_weight: Option<Decimal>,
+    ) -> Self {
+        PathVersion {
+            timestamp,
+            gross_weight,
+            exact_gross_weight,
+        }
+    }
 }
 
 impl Path {
     pub fn new(
         start_node: Rc<Node>,
         end_node: Rc<Node>,
-        weight: f32,
+        weight: f64,
+        exact_weight: Option<Decimal>,
         timestamp: DateTime<Utc>,
         factor_type: Factor,
     ) -> Self {
@@ -58,18 +95,62 @@ impl Path {
             start_node,
             end_node,
             weight,
+            exact_weight,
+            gross_weight: weight,
+            exact_gross_weight: exact_weight,
             timestamp,
             factor_type,
+            history: vec![PathVersion::new(timestamp, weight, exact_weight)],
+            halted: false,
         }
     }
+
+    // Adds a quote to the history, in timestamp order, unless one with the same
+    // timestamp is already there.
+    pub fn record(&mut self, version: PathVersion) {
+        if let Err(i) = self
+            .history
+            .binary_search_by(|v| v.timestamp.cmp(&version.timestamp))
+        {
+            self.history.insert(i, version);
+        }
+    }
+
+    // The last quote received for a time no later than `at`.
+    pub fn version_at(&self, at: DateTime<Utc>) -> Option<&PathVersion> {
+        self.history.iter().rev().find(|v| v.timestamp <= at)
+    }
+
+    pub fn is_trade(&self) -> bool {
+        self.factor_type == Factor::Forward || self.factor_type == Factor::Backward
+    }
+
+    // Derives the weight from the gross weight less a trading fee.
+    pub fn apply_fee(&mut self, fee: Decimal) {
+        let kept = Decimal::one().checked_sub(fee);
+        self.weight = self.gross_weight * kept.map_or(0.0, |k| k.to_f64());
+        self.exact_weight = self
+            .exact_gross_weight
+            .and_then(|w| kept.and_then(|k| w.checked_mul(k)));
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ExchangeRateRequest {
     pub source_exchange: String,
     pub source_currency: String,
     pub destination_exchange: String,
     pub destination_currency: String,
+    // how many of the best loop-free routes to report
+    pub route_count: usize,
+    // longest route allowed, in trades and transfers
+    pub max_hops: Option<usize>,
+    // exchanges the route must avoid, and the only ones it may use if set;
+    // the source and destination exchanges are always allowed
+    pub excluded_exchanges: Vec<String>,
+    pub only_exchanges: Option<Vec<String>>,
+    // answer with the quotes known at that time instead of the latest ones
+    pub at: Option<DateTime<Utc>>,
 }
 
 impl ExchangeRateRequest {
@@ -84,18 +165,77 @@ impl ExchangeRateRequest {
             source_currency,
             destination_exchange,
             destination_currency,
+            route_count: 1,
+            max_hops: None,
+            excluded_exchanges: vec![],
+            only_exchanges: None,
+            at: None,
+        }
+    }
+
+    pub fn with_at(mut self, at: Option<DateTime<Utc>>) -> Self {
+        self.at = at;
+        self
+    }
+
+    // The same request against the latest quotes.
+    pub fn without_at(&self) -> Self {
+        ExchangeRateRequest {
+            source_exchange: self.source_exchange.clone(),
+            source_currency: self.source_currency.clone(),
+            destination_exchange: self.destination_exchange.clone(),
+            destination_currency: self.destination_currency.clone(),
+            route_count: self.route_count,
+            max_hops: self.max_hops,
+            excluded_exchanges: self.excluded_exchanges.clone(),
+            only_exchanges: self.only_exchanges.clone(),
+            at: None,
         }
     }
+
+    pub fn with_route_count(mut self, route_count: usize) -> Self {
+        self.route_count = route_count;
+        self
+    }
+
+    pub fn with_max_hops(mut self, max_hops: Option<usize>) -> Self {
+        self.max_hops = max_hops;
+        self
+    }
+
+    pub fn with_exchange_filters(
+        mut self,
+        excluded_exchanges: Vec<String>,
+        only_exchanges: Option<Vec<String>>,
+    ) -> Self {
+        self.excluded_exchanges = excluded_exchanges;
+        self.only_exchanges = only_exchanges;
+        self
+    }
+
+    pub fn has_exchange_filter(&self) -> bool {
+        !self.excluded_exchanges.is_empty() || self.only_exchanges.is_some()
+    }
+
+    pub fn allows_exchange(&self, exchange: &str) -> bool {
+        !self.excluded_exchanges.iter().any(|e| e == exchange)
+            && self
+                .only_exchanges
+                .as_ref()
+                .is_none_or(|only| only.iter().any(|e| e == exchange))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PriceUpdateRequest {
     pub timestamp: DateTime<Utc>,
     pub exchange: String,
     pub source_currency: String,
     pub destination_currency: String,
-    pub forward_factor: f32,
-    pub backward_factor: f32,
+    pub forward_factor: f64,
+    pub backward_factor: f64,
+    pub exact_forward_factor: Option<Decimal>,
+    pub exact_backward_factor: Option<Decimal>,
 }
 
 impl PriceUpdateRequest {
@@ -104,8 +244,8 @@ impl PriceUpdateRequest {
         exchange: String,
         source_currency: String,
         destination_currency: String,
-        forward_factor: f32,
-        backward_factor: f32,
+        forward_factor: f64,
+        backward_factor: f64,
     ) -> Self {
         PriceUpdateRequest {
             timestamp,
@@ -114,8 +254,21 @@ impl PriceUpdateRequest {
             destination_currency,
             forward_factor,
             backward_factor,
+            exact_forward_factor: Decimal::from_f64(forward_factor),
+            exact_backward_factor: Decimal::from_f64(backward_factor),
         }
     }
+
+    // Keeps the factors exactly as they were typed, for decimal mode.
+    pub fn with_exact_factors(
+        mut self,
+        forward_factor: Option<Decimal>,
+        backward_factor: Option<Decimal>,
+    ) -> Self {
+        self.exact_forward_factor = forward_factor;
+        self.exact_backward_factor = backward_factor;
+        self
+    }
 }
 
 impl PartialEq for PriceUpdateRequest {
@@ -126,11 +279,67 @@ impl PartialEq for PriceUpdateRequest {
             && self.destination_currency == other.destination_currency
             && self.forward_factor == other.forward_factor
             && self.backward_factor == other.backward_factor
+            && self.exact_forward_factor == other.exact_forward_factor
+            && self.exact_backward_factor == other.exact_backward_factor
     }
 }
 
 impl Eq for PriceUpdateRequest {}
 
+// How the binary was started.
+#[derive(Debug, PartialEq, Default)]
+pub struct CommandLineOptions {
+    // read commands without banner, prompt or colours, even from a terminal
+    pub batch: bool,
+    // read the plain line protocol instead of commands; implies batch
+    pub protocol: bool,
+    // price updates are logged here before they are applied
+    pub wal_file: Option<String>,
+    // loaded at startup, and written by compaction
+    pub snapshot_file: Option<String>,
+    pub format: OutputFormat,
+}
+
+// How results and errors are printed.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    // one JSON object per line, without colours
+    Json,
+}
+
+// A trading pair on one exchange, in either order.
+#[derive(Debug, PartialEq)]
+pub struct PairRequest {
+    pub exchange: String,
+    pub source_currency: String,
+    pub destination_currency: String,
+}
+
+impl PairRequest {
+    pub fn new(exchange: String, source_currency: String, destination_currency: String) -> Self {
+        PairRequest {
+            exchange,
+            source_currency,
+            destination_currency,
+        }
+    }
+}
+
+// What a price update did to the graph.
+#[derive(Debug, PartialEq)]
+pub enum UpdateOutcome {
+    // a pair not seen before, with the number of filled-up links to other
+    // exchanges made for its new nodes
+    Inserted(usize),
+    Updated,
+    // older than the stored quote; kept in the history only
+    IgnoredStale,
+    // same timestamp as the stored quote
+    IgnoredDuplicate,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExchangeCurrencyPair {
     pub exchange: String,
@@ -143,14 +352,166 @@ impl ExchangeCurrencyPair {
     }
 }
 
+// The quote a route takes from one node to the next.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub weight: f64,
+    pub exact_weight: Option<Decimal>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Hop {
+    pub fn new(weight: f64, exact_weight: Option<Decimal>, timestamp: DateTime<Utc>) -> Self {
+        Hop {
+            weight,
+            exact_weight,
+            timestamp,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct OptimalRateWithPath {
-    pub rate: f32,
+    // net of trading fees
+    pub rate: f64,
+    // only set in decimal mode, already rounded
+    pub exact_rate: Option<Decimal>,
+    // before trading fees
+    pub gross_rate: f64,
+    pub exact_gross_rate: Option<Decimal>,
+    // age of the oldest quote on the route, counted from the newest quote known
+    pub oldest_edge_age: Option<Duration>,
+    // the better rate a route through halted pairs would give; only set on the
+    // best route
+    pub halted_rate: Option<f64>,
     pub paths: VecDeque<ExchangeCurrencyPair>,
+    // `hops[i]` leads from `paths[i]` to `paths[i + 1]`
+    pub hops: Vec<Hop>,
+    // set when a profitable cycle lies between source and destination and no hop
+    // limit applies; the rate is then unbounded and `paths` is the best route
+    // visiting each node once
+    pub arbitrage_cycle: Option<ArbitrageCycle>,
+    // set when the search for `paths` was cut short, so a better route may exist
+    pub approximate: bool,
 }
 
 impl OptimalRateWithPath {
-    pub fn new(rate: f32, paths: VecDeque<ExchangeCurrencyPair>) -> Self {
-        OptimalRateWithPath { rate, paths }
+    pub fn new(
+        rate: f64,
+        exact_rate: Option<Decimal>,
+        paths: VecDeque<ExchangeCurrencyPair>,
+        arbitrage_cycle: Option<ArbitrageCycle>,
+    ) -> Self {
+        OptimalRateWithPath {
+            rate,
+            exact_rate,
+            gross_rate: rate,
+            exact_gross_rate: exact_rate,
+            oldest_edge_age: None,
+            halted_rate: None,
+            paths,
+            hops: vec![],
+            arbitrage_cycle,
+            approximate: false,
+        }
+    }
+
+    pub fn with_approximate(mut self, approximate: bool) -> Self {
+        self.approximate = approximate;
+        self
+    }
+
+    pub fn with_hops(mut self, hops: Vec<Hop>) -> Self {
+        self.hops = hops;
+        self
+    }
+
+    pub fn with_gross_rate(mut self, gross_rate: f64, exact_gross_rate: Option<Decimal>) -> Self {
+        self.gross_rate = gross_rate;
+        self.exact_gross_rate = exact_gross_rate;
+        self
+    }
+
+    pub fn with_oldest_edge_age(mut self, oldest_edge_age: Option<Duration>) -> Self {
+        self.oldest_edge_age = oldest_edge_age;
+        self
+    }
+}
+
+// Best rates between nodes: `rates[i][j]` goes from `nodes[i]` to `nodes[j]`,
+// and is `None` on the diagonal and where no route exists.
+#[derive(Debug)]
+pub struct RateMatrix {
+    pub nodes: Vec<ExchangeCurrencyPair>,
+    pub rates: Vec<Vec<Option<OptimalRateWithPath>>>,
+}
+
+impl RateMatrix {
+    pub fn new(
+        nodes: Vec<ExchangeCurrencyPair>,
+        rates: Vec<Vec<Option<OptimalRateWithPath>>>,
+    ) -> Self {
+        RateMatrix { nodes, rates }
+    }
+
+    // Node names as "EXCHANGE CURRENCY".
+    pub fn labels(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .map(|n| format!("{} {}", n.exchange, n.currency))
+            .collect()
+    }
+
+    // Rates as printed, exact ones in decimal mode; unbounded rates are marked
+    // with a `*`, approximate ones with a `~`, missing ones left empty.
+    pub fn cells(&self) -> Vec<Vec<String>> {
+        self.rates
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|r| match r {
+                        Some(r) => {
+                            let rate = match r.exact_rate {
+                                Some(exact_rate) => exact_rate.to_string(),
+                                None => r.rate.to_string(),
+                            };
+                            let rate = match r.arbitrage_cycle {
+                                Some(_) => format!("{}*", rate),
+                                None => rate,
+                            };
+                            if r.approximate {
+                                format!("{}~", rate)
+                            } else {
+                                rate
+                            }
+                        }
+                        None => String::new(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Source nodes down the first column, destinations along the first row.
+    pub fn to_csv(&self) -> String {
+        let labels = self.labels();
+        let mut csv = format!(",{}\n", labels.join(","));
+        for (label, row) in labels.iter().zip(self.cells()) {
+            csv.push_str(&format!("{},{}\n", label, row.join(",")));
+        }
+        csv
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArbitrageCycle {
+    pub gain: f64,
+    // starts and ends at the same exchange and currency
+    pub paths: VecDeque<ExchangeCurrencyPair>,
+}
+
+impl ArbitrageCycle {
+    pub fn new(gain: f64, paths: VecDeque<ExchangeCurrencyPair>) -> Self {
+        ArbitrageCycle { gain, paths }
     }
 }
@@ -4,13 +4,28 @@ quick_error! {
     #[derive(Debug, PartialEq)]
     pub enum GraphError{
         PathNotFound{
-            description("No path found at the moment. System doesn't have enough data to provide answer to your request.")
+            display("No path found at the moment. System doesn't have enough data to provide answer to your request.")
         }
         InvalidPath{
-            description("Invalid request. Source exchange and currency should not be the same as destination's")
+            display("Invalid request. Source exchange and currency should not be the same as destination's")
         }
         Critical{
-            description("There is an a critical error occured inside the system. Please wipe out all the existing data and continue using the system.")
+            display("There is an a critical error occured inside the system. Please wipe out all the existing data and continue using the system.")
+        }
+        DecimalOverflow{
+            display("The exact rate of the best path is too large to report at this scale. Please use a smaller scale or switch off decimal mode.")
+        }
+        RouteHalted{
+            display("Every route is currently halted. Resume a pair on it to get a rate.")
+        }
+        UnknownNode{
+            display("No such currency on that exchange")
+        }
+        UnknownPair{
+            display("No such pair on that exchange")
+        }
+        UnknownExchange{
+            display("No such exchange")
         }
     }
 }
@@ -19,25 +34,22 @@ quick_error! {
     #[derive(Debug, PartialEq)]
     pub enum PriceUpdateRequestValidationError{
         InvalidArgumentNumber{
-            description("Invalid request : the number of price-update-request arguments must be 6")
+            display("Invalid request : the number of price-update-request arguments must be 6")
         }
         SameSourceDestinationCurrency{
-            description("The currency of Source should not be the same as that of Destination")
+            display("The currency of Source should not be the same as that of Destination")
         }
         InvalidTimestamp{
-            description("Invalid timestamp")
+            display("Invalid timestamp")
         }
         InvalidForwardfactor{
-            description("Invalid forward factor")
+            display("Invalid forward factor")
         }
         InvalidBackwardfactor{
-            description("Invalid backward factor")
-        }
-        ForwardBackwardFactorMultiplyError{
-            description("The product of forward factor and backward factor should be less than or equal to 1")
+            display("Invalid backward factor")
         }
-        CrossForwardBackwardFactorMultiplyError{
-            description("Invalid input based on the current algorithm of the system. The algorithm works only when the product of forward and backward factor of each path is less than or equal to 1")
+        FactorTooPrecise{
+            display("Invalid factor : it has too many digits to be kept exactly")
         }
     }
 }
@@ -46,7 +58,389 @@ quick_error! {
     #[derive(Debug, PartialEq)]
     pub enum ExchangeRateRequestValidationError{
         InvalidArgumentNumber{
-            description("Invalid request : the number of exchange-rate-request arguments must be 4")
+            display("Invalid request : the number of exchange-rate-request arguments must be 4, or 5 with the number of routes")
+        }
+        InvalidRouteCount{
+            display("Invalid number of routes : it must be a whole number above 0")
+        }
+        InvalidMaxHops{
+            display("Invalid maximum number of hops : it must be a whole number above 0")
+        }
+        InvalidExchangeList(option: String){
+            display("Invalid exchange list : {} takes exchanges separated by commas", option)
+        }
+        UnknownOption(option: String){
+            display("Unknown option : {}", option)
+        }
+        InvalidTimestamp{
+            display("Invalid timestamp : a point-in-time request looks like :r@2017-11-01T10:00:00Z")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum DecimalModeValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : decimal mode takes a scale and an optional rounding rule, or off")
+        }
+        ScaleOutOfRange{
+            display("Invalid scale : it must be a whole number between 0 and 28")
+        }
+        UnknownRounding{
+            display("Invalid rounding rule : it must be one of half-up, half-even, down or up")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum DecimalError{
+        InvalidFormat{
+            display("Invalid decimal number")
+        }
+        Overflow{
+            display("The decimal number exceeds the supported precision")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum TransferCostValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : transfer cost takes a currency, two exchanges, a proportional fee and a flat fee")
+        }
+        SameExchange{
+            display("Transfers must be between two different exchanges")
+        }
+        InvalidProportionalFee{
+            display("Invalid proportional fee : it must be a decimal number not below 0")
+        }
+        InvalidFlatFee{
+            display("Invalid flat fee : it must be a decimal number not below 0")
+        }
+        FeeTooHigh{
+            display("The fees would take the whole transferred amount")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum TransferCostFileError{
+        InvalidArgumentNumber{
+            display("Invalid request : loading transfer costs takes a file name")
+        }
+        Unreadable(reason: String){
+            display("The transfer cost file can't be read : {}", reason)
+        }
+        InvalidLine(line: usize, err: TransferCostValidationError){
+            display("Line {} of the transfer cost file : {}", line, err)
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum TradingFeeValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : a trading fee takes an exchange, an optional currency pair and the fee")
+        }
+        SameCurrency{
+            display("The two currencies of a pair must differ")
+        }
+        InvalidFee{
+            display("Invalid fee : it must be a decimal number from 0 up to, but not including, 1")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum LinkValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : linking takes two exchanges and a currency")
+        }
+        SameExchange{
+            display("Links must be between two different exchanges")
+        }
+        InvalidAutoLinkMode{
+            display("Invalid request : auto-linking is either on or off")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum TtlValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : the time-to-live takes a number of seconds, or off")
+        }
+        InvalidMaxAge{
+            display("Invalid time-to-live : it must be a whole number of seconds above 0")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum PairValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : a pair is given as an exchange and two currencies")
+        }
+        SameCurrency{
+            display("The two currencies of a pair must differ")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum ExchangeValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : this takes a single exchange")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum NodeValidationError{
+        InvalidArgumentNumber{
+            display("Invalid request : this takes an exchange and a currency")
         }
     }
 }
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum RateMatrixError{
+        MissingFileName{
+            display("Invalid request : --csv takes a file name")
+        }
+        Unwritable(reason: String){
+            display("The matrix can't be written : {}", reason)
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum SnapshotError{
+        InvalidArgumentNumber{
+            display("Invalid request : saving or loading takes a file name")
+        }
+        Unreadable(reason: String){
+            display("The snapshot can't be read : {}", reason)
+        }
+        Unwritable(reason: String){
+            display("The snapshot can't be written : {}", reason)
+        }
+        NotASnapshot{
+            display("The file isn't a snapshot")
+        }
+        UnsupportedVersion(version: String){
+            display("Snapshot version {} isn't supported", version)
+        }
+        InvalidLine(line: usize, reason: String){
+            display("Line {} of the snapshot : {}", line, reason)
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum WalError{
+        Unreadable(reason: String){
+            display("The write-ahead log can't be read : {}", reason)
+        }
+        Unwritable(reason: String){
+            display("The write-ahead log can't be written : {}", reason)
+        }
+        InvalidRecord(line: usize, err: PriceUpdateRequestValidationError){
+            display("Record {} of the write-ahead log : {}", line, err)
+        }
+        UnreplayableCommand(command: String, reason: String){
+            display("The write-ahead log can't replay {} : {}", command, reason)
+        }
+        CompactionUnavailable{
+            display("Compaction needs the binary to be started with both --wal and --snapshot")
+        }
+        LoadUnavailable{
+            display("With --wal, loading a snapshot needs --snapshot too : the log alone can't record it")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum CommandLineError{
+        MissingValue(option: String){
+            display("Invalid command line : {} takes a value", option)
+        }
+        UnknownOption(option: String){
+            display("Invalid command line : unknown option {}", option)
+        }
+        UnknownFormat(format: String){
+            display("Invalid command line : unknown format {}, expected text or json", format)
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum CommandError{
+        UnknownCommand(command: String){
+            display("Invalid Command : {}", command)
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum ProtocolError{
+        PriceUpdate(err: PriceUpdateRequestValidationError){
+            from()
+            cause(err)
+            display("{}", err)
+        }
+        ExchangeRate(err: ExchangeRateRequestValidationError){
+            from()
+            cause(err)
+            display("{}", err)
+        }
+    }
+}
+
+// Every error has a `kind` for machine-readable output : its enum's namespace
+// and the name its variant had when it was added, so it stays the same when a
+// variant is renamed and two enums never share one. Errors wrapping another
+// one take its kind.
+macro_rules! kinds {
+    (@kind $namespace:literal, $kind:literal) => {
+        concat!($namespace, ".", $kind)
+    };
+    (@kind $namespace:literal, $inner:ident) => {
+        $inner.kind()
+    };
+    ($($error:ident in $namespace:literal {
+        $($variant:ident $({ $($field:tt)* })? => $kind:tt,)*
+    })*) => {
+        $(
+            impl $error {
+                pub fn kind(&self) -> &'static str {
+                    match self {
+                        $($error::$variant { $($($field)*,)? .. } => kinds!(@kind $namespace, $kind),)*
+                    }
+                }
+            }
+        )*
+    };
+}
+
+kinds! {
+    GraphError in "graph" {
+        PathNotFound => "path_not_found",
+        InvalidPath => "invalid_path",
+        Critical => "critical",
+        DecimalOverflow => "decimal_overflow",
+        RouteHalted => "route_halted",
+        UnknownNode => "unknown_node",
+        UnknownPair => "unknown_pair",
+        UnknownExchange => "unknown_exchange",
+    }
+    PriceUpdateRequestValidationError in "price_update" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        SameSourceDestinationCurrency => "same_source_destination_currency",
+        InvalidTimestamp => "invalid_timestamp",
+        InvalidForwardfactor => "invalid_forwardfactor",
+        InvalidBackwardfactor => "invalid_backwardfactor",
+        FactorTooPrecise => "factor_too_precise",
+    }
+    ExchangeRateRequestValidationError in "exchange_rate" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        InvalidRouteCount => "invalid_route_count",
+        InvalidMaxHops => "invalid_max_hops",
+        InvalidExchangeList => "invalid_exchange_list",
+        UnknownOption => "unknown_option",
+        InvalidTimestamp => "invalid_timestamp",
+    }
+    DecimalModeValidationError in "decimal_mode" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        ScaleOutOfRange => "scale_out_of_range",
+        UnknownRounding => "unknown_rounding",
+    }
+    DecimalError in "decimal" {
+        InvalidFormat => "invalid_format",
+        Overflow => "overflow",
+    }
+    TransferCostValidationError in "transfer_cost" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        SameExchange => "same_exchange",
+        InvalidProportionalFee => "invalid_proportional_fee",
+        InvalidFlatFee => "invalid_flat_fee",
+        FeeTooHigh => "fee_too_high",
+    }
+    TransferCostFileError in "transfer_cost_file" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        Unreadable => "unreadable",
+        InvalidLine { 1: err } => err,
+    }
+    TradingFeeValidationError in "trading_fee" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        SameCurrency => "same_currency",
+        InvalidFee => "invalid_fee",
+    }
+    LinkValidationError in "link" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        SameExchange => "same_exchange",
+        InvalidAutoLinkMode => "invalid_auto_link_mode",
+    }
+    TtlValidationError in "ttl" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        InvalidMaxAge => "invalid_max_age",
+    }
+    PairValidationError in "pair" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        SameCurrency => "same_currency",
+    }
+    ExchangeValidationError in "exchange" {
+        InvalidArgumentNumber => "invalid_argument_number",
+    }
+    NodeValidationError in "node" {
+        InvalidArgumentNumber => "invalid_argument_number",
+    }
+    RateMatrixError in "rate_matrix" {
+        MissingFileName => "missing_file_name",
+        Unwritable => "unwritable",
+    }
+    SnapshotError in "snapshot" {
+        InvalidArgumentNumber => "invalid_argument_number",
+        Unreadable => "unreadable",
+        Unwritable => "unwritable",
+        NotASnapshot => "not_a_snapshot",
+        UnsupportedVersion => "unsupported_version",
+        InvalidLine => "invalid_line",
+    }
+    WalError in "wal" {
+        Unreadable => "unreadable",
+        Unwritable => "unwritable",
+        UnreplayableCommand => "unreplayable_command",
+        CompactionUnavailable => "compaction_unavailable",
+        LoadUnavailable => "load_unavailable",
+        InvalidRecord { 1: err } => err,
+    }
+    CommandLineError in "command_line" {
+        MissingValue => "missing_value",
+        UnknownOption => "unknown_option",
+        UnknownFormat => "unknown_format",
+    }
+    CommandError in "command" {
+        UnknownCommand => "unknown_command",
+    }
+    ProtocolError in "protocol" {
+        PriceUpdate { 0: err } => err,
+        ExchangeRate { 0: err } => err,
+    }
+}
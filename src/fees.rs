@@ -0,0 +1,102 @@
+use crate::decimal::*;
+use crate::error::*;
+use std::collections::HashMap;
+
+// A taker fee, as a share of the traded amount, for every pair of an exchange
+// or for a single pair of it. A fee set for a pair overrides the exchange fee.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradingFeeRequest {
+    pub exchange: String,
+    pub pair: Option<(String, String)>,
+    pub fee: Decimal,
+}
+
+impl TradingFeeRequest {
+    pub fn new(
+        exchange: String,
+        pair: Option<(String, String)>,
+        fee: Decimal,
+    ) -> Result<TradingFeeRequest, TradingFeeValidationError> {
+        if fee.is_negative()
+            || !Decimal::one()
+                .checked_sub(fee)
+                .is_some_and(|r| r.is_positive())
+        {
+            return Err(TradingFeeValidationError::InvalidFee);
+        }
+        Ok(TradingFeeRequest {
+            exchange,
+            pair,
+            fee,
+        })
+    }
+}
+
+// Trading fee schedule of every exchange. Trades on exchanges missing from it
+// are free.
+#[derive(Debug, Clone, Default)]
+pub struct TradingFees {
+    exchange_fees: HashMap<String, Decimal>,
+    // (exchange, currency, currency), currencies in alphabetical order
+    pair_fees: HashMap<(String, String, String), Decimal>,
+}
+
+impl TradingFees {
+    pub fn new() -> TradingFees {
+        TradingFees {
+            exchange_fees: HashMap::new(),
+            pair_fees: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, request: &TradingFeeRequest) {
+        match &request.pair {
+            Some((a, b)) => {
+                self.pair_fees
+                    .insert(pair_key(&request.exchange, a, b), request.fee);
+            }
+            None => {
+                self.exchange_fees
+                    .insert(request.exchange.clone(), request.fee);
+            }
+        }
+    }
+
+    // Fee of a trade between `a` and `b` on `exchange`, in either direction.
+    pub fn fee(&self, exchange: &str, a: &str, b: &str) -> Decimal {
+        self.pair_fees
+            .get(&pair_key(exchange, a, b))
+            .or_else(|| self.exchange_fees.get(exchange))
+            .cloned()
+            .unwrap_or_else(Decimal::zero)
+    }
+
+    // Every entry, exchange fees before the pair fees of the same exchange.
+    pub fn to_requests(&self) -> Vec<TradingFeeRequest> {
+        let mut requests: Vec<TradingFeeRequest> = self
+            .exchange_fees
+            .iter()
+            .map(|(exchange, fee)| TradingFeeRequest {
+                exchange: exchange.clone(),
+                pair: None,
+                fee: *fee,
+            })
+            .chain(
+                self.pair_fees
+                    .iter()
+                    .map(|((exchange, a, b), fee)| TradingFeeRequest {
+                        exchange: exchange.clone(),
+                        pair: Some((a.clone(), b.clone())),
+                        fee: *fee,
+                    }),
+            )
+            .collect();
+        requests.sort_by(|a, b| (&a.exchange, &a.pair).cmp(&(&b.exchange, &b.pair)));
+        requests
+    }
+}
+
+fn pair_key(exchange: &str, a: &str, b: &str) -> (String, String, String) {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    (exchange.to_owned(), first.to_owned(), second.to_owned())
+}
@@ -1,26 +1,461 @@
+use crate::all_pairs::*;
+use crate::arbitrage::{self, Route};
+use crate::decimal::*;
 use crate::domain::*;
 use crate::error::*;
+use crate::fees::*;
+use crate::links::*;
+use crate::routes;
+use crate::snapshot::*;
+use crate::transfer::*;
 use chrono::prelude::*;
-use std::collections::VecDeque;
+use chrono::Duration;
+use std::cell::{RefCell, RefMut};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
 
 pub type GraphResult = Result<OptimalRateWithPath, GraphError>;
+pub type GraphResults = Result<Vec<OptimalRateWithPath>, GraphError>;
 
 #[derive(Debug)]
 pub struct Graph {
     nodes: Vec<Rc<Node>>,
+    // exchange -> currency -> index into `nodes`
+    node_indices: HashMap<String, HashMap<String, usize>>,
     paths: Vec<Path>,
+    // node index -> indices into `paths` of the paths starting at that node
+    adjacency: Vec<Vec<usize>>,
+    // best rates between all nodes, built lazily and then maintained by `update`
+    all_pairs: RefCell<Option<AllPairs>>,
+    decimal_mode: Option<DecimalMode>,
+    // applied to the filled-up paths between exchanges
+    transfer_costs: TransferCosts,
+    // applied to the forward and backward paths of every exchange
+    trading_fees: TradingFees,
+    // which same-currency nodes on different exchanges get filled-up paths
+    links: Links,
+    // quotes older than this, counted back from the newest one, aren't routed on
+    max_age: Option<Duration>,
+    // set on graphs rebuilt for a point-in-time query; ages are counted from it
+    as_of: Option<DateTime<Utc>>,
+    // id of the next node inserted
+    next_node_id: usize,
+}
+
+// The best route of a rate request, before its rate is worked out.
+struct TopRoute {
+    route: Route,
+    // the profitable cycle that makes the rate unbounded, if any
+    arbitrage_cycle: Option<ArbitrageCycle>,
+    // the nodes and paths the request may route through
+    usable: Vec<bool>,
+    usable_paths: Vec<bool>,
 }
 
 impl Graph {
     pub fn new() -> Graph {
         Graph {
             nodes: Vec::<Rc<Node>>::new(),
+            node_indices: HashMap::new(),
             paths: Vec::<Path>::new(),
+            adjacency: Vec::new(),
+            all_pairs: RefCell::new(None),
+            decimal_mode: None,
+            transfer_costs: TransferCosts::new(),
+            trading_fees: TradingFees::new(),
+            links: Links::new(),
+            max_age: None,
+            as_of: None,
+            next_node_id: 0,
+        }
+    }
+
+    // Ignores quotes older than `max_age` in rate requests, or none if `None`.
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+        self.max_age = max_age;
+    }
+
+    // Switches exact decimal rates on (with their final rounding) or off.
+    pub fn set_decimal_mode(&mut self, mode: Option<DecimalMode>) {
+        self.decimal_mode = mode;
+    }
+
+    // Records the cost of a transfer and reprices its path if it already exists.
+    pub fn set_transfer_cost(&mut self, request: &TransferCostRequest) {
+        self.transfer_costs.set(request);
+
+        let from = self.find_node_with(&request.from_exchange, &request.currency);
+        let to = self.find_node_with(&request.to_exchange, &request.currency);
+        let path = match (from, to) {
+            (Some(from), Some(to)) => self.find_path(from.index, to.index),
+            _ => None,
+        };
+        if let Some(index) = path {
+            let p = &mut self.paths[index];
+            if p.factor_type != Factor::FilledUpForward && p.factor_type != Factor::FilledUpBackward
+            {
+                return;
+            }
+            let (old_weight, new_weight) = (p.weight, request.cost.weight());
+            p.weight = new_weight;
+            p.exact_weight = request.cost.exact_weight();
+            if old_weight != new_weight {
+                let change = (p.start_node.index, p.end_node.index, old_weight, new_weight);
+                self.propagate_weight_changes(&[change]);
+            }
+        }
+    }
+
+    pub fn set_link(&mut self, request: &LinkRequest) {
+        self.links.set(request);
+        self.relink();
+    }
+
+    // Switches the automatic linking of same-currency nodes across exchanges on
+    // or off; only the pairs linked by hand stay linked while it's off.
+    pub fn set_auto_link(&mut self, auto_link: bool) {
+        self.links.set_auto_link(auto_link);
+        self.relink();
+    }
+
+    pub fn is_auto_linking(&self) -> bool {
+        self.links.auto_link()
+    }
+
+    pub fn get_links(&self) -> Vec<LinkRequest> {
+        self.links.to_requests()
+    }
+
+    pub fn get_transfer_costs(&self) -> Vec<TransferCostRequest> {
+        self.transfer_costs.to_requests()
+    }
+
+    // Records a trading fee and reprices the trades it applies to.
+    pub fn set_trading_fee(&mut self, request: &TradingFeeRequest) {
+        self.trading_fees.set(request);
+
+        let mut weight_changes = vec![];
+        for p in self.paths.iter_mut() {
+            let (exchange, source, destination) = (
+                &p.start_node.exchange,
+                &p.start_node.currency,
+                &p.end_node.currency,
+            );
+            let in_pair = match &request.pair {
+                Some((a, b)) => {
+                    (source == a && destination == b) || (source == b && destination == a)
+                }
+                None => true,
+            };
+            if !p.is_trade() || *exchange != request.exchange || !in_pair {
+                continue;
+            }
+            let old_weight = p.weight;
+            p.apply_fee(self.trading_fees.fee(exchange, source, destination));
+            if p.weight != old_weight {
+                weight_changes.push((p.start_node.index, p.end_node.index, old_weight, p.weight));
+            }
         }
+        self.propagate_weight_changes(&weight_changes);
     }
 
+    pub fn get_trading_fees(&self) -> Vec<TradingFeeRequest> {
+        self.trading_fees.to_requests()
+    }
+
+    #[cfg(test)]
     pub fn get_top_result(&self, exchange_request: &ExchangeRateRequest) -> GraphResult {
+        self.get_top_results(exchange_request)
+            .map(|mut results| results.swap_remove(0))
+    }
+
+    // The `route_count` best loop-free routes of the request, best rate first.
+    pub fn get_top_results(&self, exchange_request: &ExchangeRateRequest) -> GraphResults {
+        if let Some(at) = exchange_request.at {
+            return self
+                .as_of(at)
+                .get_top_results(&exchange_request.without_at());
+        }
+        let top = match self.find_top_route(exchange_request, true) {
+            Err(GraphError::PathNotFound)
+                if self.find_top_route(exchange_request, false).is_ok() =>
+            {
+                return Err(GraphError::RouteHalted)
+            }
+            top => top?,
+        };
+        let arbitrage_cycle = top.arbitrage_cycle;
+        let mut results = routes::k_best_routes(
+            &self.paths,
+            &self.adjacency,
+            &top.usable,
+            &top.usable_paths,
+            top.route,
+            exchange_request.route_count,
+            exchange_request.max_hops,
+        )
+        .iter()
+        .map(|r| {
+            self.to_optimal_rate(&r.nodes, arbitrage_cycle.clone())
+                .map(|o| o.with_approximate(r.approximate))
+        })
+        .collect::<GraphResults>()?;
+        if let Some(best) = results.first_mut() {
+            best.halted_rate = self.halted_rate(exchange_request, best.rate);
+        }
+        Ok(results)
+    }
+
+    // The best rate from one node to every other node it can reach, sorted by
+    // destination exchange and currency.
+    pub fn get_rates_from(&self, source: &ExchangeCurrencyPair) -> GraphResults {
+        let source = self
+            .find_node_with(&source.exchange, &source.currency)
+            .ok_or(GraphError::UnknownNode)?;
+        let targets: Vec<Rc<Node>> = self
+            .sorted_nodes()
+            .into_iter()
+            .filter(|n| n.index != source.index)
+            .collect();
+        Ok(self
+            .best_rates_from(&source, &targets, &self.routable_paths())?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    // The best rates between the nodes of the given currencies, or of all
+    // currencies if none is given.
+    pub fn get_rate_matrix(&self, currencies: &[String]) -> Result<RateMatrix, GraphError> {
+        let nodes: Vec<Rc<Node>> = self
+            .sorted_nodes()
+            .into_iter()
+            .filter(|n| currencies.is_empty() || currencies.contains(&n.currency))
+            .collect();
+        let usable_paths = self.routable_paths();
+        let rates = nodes
+            .iter()
+            .map(|from| self.best_rates_from(from, &nodes, &usable_paths))
+            .collect::<Result<Vec<_>, GraphError>>()?;
+        Ok(RateMatrix::new(
+            nodes
+                .iter()
+                .map(|n| ExchangeCurrencyPair::new(n.exchange.clone(), n.currency.clone()))
+                .collect(),
+            rates,
+        ))
+    }
+
+    // Nodes by exchange, then currency.
+    fn sorted_nodes(&self) -> Vec<Rc<Node>> {
+        let mut nodes = self.nodes.clone();
+        nodes.sort_by(|a, b| (&a.exchange, &a.currency).cmp(&(&b.exchange, &b.currency)));
+        nodes
+    }
+
+    // The best route between two nodes as a single `:r` would give it, or
+    // `None` if there's no open one; what `:from` and `:matrix` must agree with.
+    #[cfg(test)]
+    fn best_rate_between(
+        &self,
+        from: &Node,
+        to: &Node,
+    ) -> Result<Option<OptimalRateWithPath>, GraphError> {
+        let request = ExchangeRateRequest::new(
+            from.exchange.clone(),
+            from.currency.clone(),
+            to.exchange.clone(),
+            to.currency.clone(),
+        );
+        match self.get_top_results(&request) {
+            Ok(mut best) => {
+                let mut best = best.swap_remove(0);
+                // the halted-pair hint is only given to `:r`
+                best.halted_rate = None;
+                Ok(Some(best))
+            }
+            Err(GraphError::PathNotFound) | Err(GraphError::RouteHalted) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Paths a route may take: fresh and not halted.
+    fn routable_paths(&self) -> Vec<bool> {
+        self.fresh_paths()
+            .iter()
+            .zip(self.paths.iter())
+            .map(|(&fresh, p)| fresh && !p.halted)
+            .collect()
+    }
+
+    // The best route from `source` to each of `targets`, or `None` where
+    // there's no open one. Read from the cached best rates when every path is
+    // usable and no cycle is profitable, and otherwise from one single-source
+    // pass; targets a profitable cycle can reach share one search for the best
+    // routes visiting each node once.
+    fn best_rates_from(
+        &self,
+        source: &Node,
+        targets: &[Rc<Node>],
+        usable_paths: &[bool],
+    ) -> Result<Vec<Option<OptimalRateWithPath>>, GraphError> {
+        let cached = !usable_paths.contains(&false) && !self.all_pairs().has_negative_cycle();
+        let (pred, looping) = if cached {
+            (vec![], vec![])
+        } else {
+            self.single_source_routes(source.index, usable_paths)
+        };
+        let simple_routes = if looping.contains(&true) {
+            arbitrage::best_simple_paths_from(
+                &self.paths,
+                &self.adjacency,
+                usable_paths,
+                source.index,
+            )
+        } else {
+            vec![]
+        };
+        let allowed = vec![true; self.nodes.len()];
+        targets
+            .iter()
+            .map(|target| {
+                if target.index == source.index {
+                    return Ok(None);
+                }
+                let (route, arbitrage_cycle) = if cached {
+                    let route = self.all_pairs().route(source.index, target.index);
+                    (route.map(Route::exact), None)
+                } else if looping[target.index] {
+                    let usable =
+                        self.nodes_between(source.index, target.index, &allowed, usable_paths);
+                    (
+                        simple_routes[target.index].clone(),
+                        self.find_arbitrage_cycle_within(&usable, usable_paths),
+                    )
+                } else {
+                    let route = route_from_pred(&pred, source.index, target.index);
+                    (route.map(Route::exact), None)
+                };
+                match route {
+                    Some(route) if route.nodes.len() > 1 => self
+                        .to_optimal_rate(&route.nodes, arbitrage_cycle)
+                        .map(|r| Some(r.with_approximate(route.approximate))),
+                    _ => Ok(None),
+                }
+            })
+            .collect()
+    }
+
+    // Bellman–Ford from `source` over the usable paths: the predecessor of
+    // each node on its best route, and the nodes a profitable cycle reachable
+    // from `source` leads to, whose best rate is unbounded.
+    fn single_source_routes(
+        &self,
+        source: usize,
+        usable_paths: &[bool],
+    ) -> (Vec<Option<usize>>, Vec<bool>) {
+        let node_count = self.nodes.len();
+        let mut cost = vec![f64::INFINITY; node_count];
+        let mut pred = vec![None; node_count];
+        cost[source] = 0.0;
+        // the nodes whose route got cheaper
+        let relax = |cost: &mut Vec<f64>, pred: &mut Vec<Option<usize>>| {
+            let mut relaxed = vec![];
+            for (p, _) in self
+                .paths
+                .iter()
+                .zip(usable_paths.iter())
+                .filter(|(_, &usable)| usable)
+            {
+                let (from, to) = (p.start_node.index, p.end_node.index);
+                let candidate = cost[from] + cost_of(p.weight);
+                if candidate < cost[to] - EPSILON {
+                    cost[to] = candidate;
+                    pred[to] = Some(from);
+                    relaxed.push(to);
+                }
+            }
+            relaxed
+        };
+        for _ in 1..node_count {
+            if relax(&mut cost, &mut pred).is_empty() {
+                break;
+            }
+        }
+
+        // anything still getting cheaper lies on or behind a profitable cycle
+        let mut looping = vec![false; node_count];
+        let mut queue: VecDeque<usize> = relax(&mut cost, &mut pred).into();
+        while let Some(n) = queue.pop_front() {
+            if looping[n] {
+                continue;
+            }
+            looping[n] = true;
+            for &p in self.adjacency[n].iter().filter(|&&p| usable_paths[p]) {
+                queue.push_back(self.paths[p].end_node.index);
+            }
+        }
+        (pred, looping)
+    }
+
+    // The rate of the best route if halted pairs were open, when it beats `rate`.
+    fn halted_rate(&self, exchange_request: &ExchangeRateRequest, rate: f64) -> Option<f64> {
+        if !self.paths.iter().any(|p| p.halted) {
+            return None;
+        }
+        self.find_top_route(exchange_request, false)
+            .and_then(|top| self.to_optimal_rate(&top.route.nodes, None))
+            .ok()
+            .map(|r| r.rate)
+            .filter(|r| r.ln() > rate.ln() + EPSILON)
+    }
+
+    // The graph as it stood at `at`: every pair quoted by then at its last quote
+    // by then, with today's fees, transfer costs and links.
+    fn as_of(&self, at: DateTime<Utc>) -> Graph {
+        let mut graph = Graph::new();
+        graph.decimal_mode = self.decimal_mode;
+        graph.transfer_costs = self.transfer_costs.clone();
+        graph.trading_fees = self.trading_fees.clone();
+        graph.links = self.links.clone();
+        graph.max_age = self.max_age;
+        graph.as_of = Some(at);
+
+        let mut requests: Vec<PriceUpdateRequest> = self
+            .paths
+            .iter()
+            .filter(|p| p.factor_type == Factor::Forward)
+            .filter_map(|forward| {
+                let backward = self
+                    .find_path(forward.end_node.index, forward.start_node.index)
+                    .and_then(|b| self.paths[b].version_at(at))?;
+                let version = forward.version_at(at)?;
+                Some(
+                    PriceUpdateRequest::new(
+                        version.timestamp,
+                        forward.start_node.exchange.clone(),
+                        forward.start_node.currency.clone(),
+                        forward.end_node.currency.clone(),
+                        version.gross_weight,
+                        backward.gross_weight,
+                    )
+                    .with_exact_factors(version.exact_gross_weight, backward.exact_gross_weight),
+                )
+            })
+            .collect();
+        requests.sort_by_key(|r| r.timestamp);
+        for request in &requests {
+            graph.update(request);
+        }
+        graph
+    }
+
+    fn find_top_route(
+        &self,
+        exchange_request: &ExchangeRateRequest,
+        skip_halted: bool,
+    ) -> Result<TopRoute, GraphError> {
         let start_node = self.find_node_with(
             &exchange_request.source_exchange,
             &exchange_request.source_currency,
@@ -33,232 +468,677 @@ impl Graph {
 
         match (start_node, end_node) {
             (Some(sn), Some(en)) => {
-                let paths = self.get_top_paths(&sn, &en);
-                if paths.is_empty() {
+                // the source and destination are allowed whatever the filters say
+                let allowed: Vec<bool> = self
+                    .nodes
+                    .iter()
+                    .map(|n| {
+                        n.index == sn.index
+                            || n.index == en.index
+                            || exchange_request.allows_exchange(&n.exchange)
+                    })
+                    .collect();
+                let usable_paths: Vec<bool> = self
+                    .fresh_paths()
+                    .iter()
+                    .zip(self.paths.iter())
+                    .map(|(&fresh, p)| fresh && !(skip_halted && p.halted))
+                    .collect();
+                let usable = self.nodes_between(sn.index, en.index, &allowed, &usable_paths);
+
+                let max_hops = exchange_request.max_hops;
+                let arbitrage_cycle = match max_hops {
+                    Some(_) => None,
+                    None => self.find_arbitrage_cycle_within(&usable, &usable_paths),
+                };
+                let constrained = max_hops.is_some()
+                    || exchange_request.has_exchange_filter()
+                    || usable_paths.contains(&false);
+                let route = if arbitrage_cycle.is_none() && !constrained {
+                    Route::exact(self.get_top_paths(&sn, &en))
+                } else {
+                    // looping through a cycle gains without bound, and the cached
+                    // best rates know neither hop limits, filters, halts nor
+                    // stale quotes, so search the usable subgraph for the best route
+                    // visiting every node once
+                    routes::best_route(
+                        &self.paths,
+                        &self.adjacency,
+                        &usable,
+                        &usable_paths,
+                        sn.index,
+                        en.index,
+                        max_hops,
+                    )
+                    .unwrap_or_else(|| Route::exact(vec![]))
+                };
+                if route.nodes.is_empty() {
                     Err(GraphError::PathNotFound)
-                } else if paths.len() == 1 {
+                } else if route.nodes.len() == 1 {
                     // check for the exchange rate request like "KRAKEN, BTC, KRAKEN, BTC"
                     Err(GraphError::InvalidPath)
                 } else {
-                    let mut rate = 1_f32;
-                    let mut pair: VecDeque<ExchangeCurrencyPair> =
-                        VecDeque::with_capacity(paths.len());
-
-                    let mut iter = paths.windows(2);
-                    while let Some(&[si, ei]) = iter.next() {
-                        let path = self.paths.iter().find(|p| {
-                            p.start_node.index.get() == si && p.end_node.index.get() == ei
-                        });
-                        if path.is_none() {
-                            return Err(GraphError::Critical);
-                        }
-
-                        let path = path.unwrap();
-                        rate *= path.weight;
-                        pair.push_back(ExchangeCurrencyPair::new(
-                            path.start_node.exchange.clone(),
-                            path.start_node.currency.clone(),
-                        ));
-                    }
-
-                    pair.push_back(ExchangeCurrencyPair::new(
-                        en.exchange.clone(),
-                        en.currency.clone(),
-                    ));
-                    Ok(OptimalRateWithPath::new(rate, pair))
+                    Ok(TopRoute {
+                        route,
+                        arbitrage_cycle,
+                        usable,
+                        usable_paths,
+                    })
                 }
             }
             _ => Err(GraphError::PathNotFound),
         }
     }
 
-    fn get_top_paths(&self, start_node: &Rc<Node>, end_node: &Rc<Node>) -> Vec<usize> {
-        let next = self.reconstruct_path();
-        let mut paths = vec![];
-        let mut u = start_node.index.get();
-        let v = end_node.index.get();
-        if next[u][v].is_some() {
-            paths.push(u);
-            while u != v {
-                u = next[u][v].unwrap();
-                paths.push(u);
+    fn to_optimal_rate(
+        &self,
+        route: &[usize],
+        arbitrage_cycle: Option<ArbitrageCycle>,
+    ) -> GraphResult {
+        let mut rate = 1_f64;
+        let mut exact_rate = Some(ExactProduct::one());
+        let mut gross_rate = 1_f64;
+        let mut exact_gross_rate = Some(ExactProduct::one());
+        let mut oldest_quote: Option<DateTime<Utc>> = None;
+        let mut pair: VecDeque<ExchangeCurrencyPair> = VecDeque::with_capacity(route.len());
+        let mut hops = Vec::with_capacity(route.len() - 1);
+
+        let mut iter = route.windows(2);
+        while let Some(&[si, ei]) = iter.next() {
+            let path = match self.find_path(si, ei) {
+                Some(p) => &self.paths[p],
+                None => return Err(GraphError::Critical),
+            };
+            rate *= path.weight;
+            exact_rate = exact_rate.and_then(|r| path.exact_weight.map(|w| r.times(w)));
+            gross_rate *= path.gross_weight;
+            exact_gross_rate =
+                exact_gross_rate.and_then(|r| path.exact_gross_weight.map(|w| r.times(w)));
+            if path.is_trade() && oldest_quote.is_none_or(|t| path.timestamp < t) {
+                oldest_quote = Some(path.timestamp);
             }
+            pair.push_back(ExchangeCurrencyPair::new(
+                path.start_node.exchange.clone(),
+                path.start_node.currency.clone(),
+            ));
+            hops.push(Hop::new(path.weight, path.exact_weight, path.timestamp));
+        }
+
+        let en = &self.nodes[route[route.len() - 1]];
+        pair.push_back(ExchangeCurrencyPair::new(
+            en.exchange.clone(),
+            en.currency.clone(),
+        ));
+        Ok(OptimalRateWithPath::new(
+            rate,
+            self.round_exact_rate(exact_rate)?,
+            pair,
+            arbitrage_cycle,
+        )
+        .with_hops(hops)
+        .with_gross_rate(gross_rate, self.round_exact_rate(exact_gross_rate)?)
+        .with_oldest_edge_age(
+            oldest_quote.and_then(|t| self.clock().map(|now| now.signed_duration_since(t))),
+        ))
+    }
+
+    // The newest quote; quote ages and the time-to-live are measured from it.
+    fn clock(&self) -> Option<DateTime<Utc>> {
+        if self.as_of.is_some() {
+            return self.as_of;
+        }
+        self.paths
+            .iter()
+            .filter(|p| p.is_trade())
+            .map(|p| p.timestamp)
+            .max()
+    }
+
+    // Marks the paths a rate request may take: all but the quotes older than the
+    // time-to-live. Transfers don't go stale.
+    fn fresh_paths(&self) -> Vec<bool> {
+        let oldest_allowed = match (self.max_age, self.clock()) {
+            (Some(max_age), Some(now)) => now - max_age,
+            _ => return vec![true; self.paths.len()],
+        };
+        self.paths
+            .iter()
+            .map(|p| !p.is_trade() || p.timestamp >= oldest_allowed)
+            .collect()
+    }
+
+    // Rounds an exact rate as decimal mode says; `None` outside decimal mode.
+    fn round_exact_rate(
+        &self,
+        exact_rate: Option<ExactProduct>,
+    ) -> Result<Option<Decimal>, GraphError> {
+        match self.decimal_mode {
+            Some(mode) => Ok(Some(
+                exact_rate
+                    .and_then(|r| r.round(mode.scale, mode.rounding))
+                    .ok_or(GraphError::DecimalOverflow)?,
+            )),
+            None => Ok(None),
         }
-        paths
     }
 
-    fn reconstruct_path(&self) -> Vec<Vec<Option<usize>>> {
-        let mut rate: Vec<Vec<f32>> = Vec::with_capacity(self.paths.len());
-        let mut next: Vec<Vec<Option<usize>>> = Vec::with_capacity(self.paths.len());
+    // Profitable cycles in the current prices, best gain first.
+    pub fn get_arbitrage_cycles(&self) -> Vec<ArbitrageCycle> {
+        let mut cycles: Vec<ArbitrageCycle> =
+            arbitrage::find_cycles(self.nodes.len(), &self.paths, |i| !self.paths[i].halted)
+                .iter()
+                .map(|cycle| self.to_arbitrage_cycle(cycle))
+                .collect();
+        cycles.sort_by(|a, b| b.gain.partial_cmp(&a.gain).unwrap_or(Ordering::Equal));
+        cycles
+    }
 
-        let len = self.paths.len();
+    // The most profitable cycle among the `usable` nodes.
+    fn find_arbitrage_cycle_within(
+        &self,
+        usable: &[bool],
+        usable_paths: &[bool],
+    ) -> Option<ArbitrageCycle> {
+        if !self.all_pairs().has_negative_cycle() {
+            return None;
+        }
+        arbitrage::find_cycles(self.nodes.len(), &self.paths, |i| {
+            let p = &self.paths[i];
+            usable_paths[i] && usable[p.start_node.index] && usable[p.end_node.index]
+        })
+        .iter()
+        .map(|cycle| self.to_arbitrage_cycle(cycle))
+        .max_by(|a, b| a.gain.partial_cmp(&b.gain).unwrap_or(Ordering::Equal))
+    }
 
-        for i in 0..len {
-            rate.push(Vec::with_capacity(len));
-            next.push(Vec::with_capacity(len));
-            for _ in 0..len {
-                rate[i].push(0.0);
-                next[i].push(None);
-            }
+    fn to_arbitrage_cycle(&self, cycle: &[usize]) -> ArbitrageCycle {
+        let mut gain = 1_f64;
+        let mut pair: VecDeque<ExchangeCurrencyPair> = VecDeque::with_capacity(cycle.len() + 1);
+        for &p in cycle.iter() {
+            let path = &self.paths[p];
+            gain *= path.weight;
+            pair.push_back(ExchangeCurrencyPair::new(
+                path.start_node.exchange.clone(),
+                path.start_node.currency.clone(),
+            ));
         }
+        if let Some(first) = pair.front().cloned() {
+            pair.push_back(first);
+        }
+        ArbitrageCycle::new(gain, pair)
+    }
 
-        for i in 0..len {
-            let from_index = self.paths[i].start_node.index.get();
-            let to_index = self.paths[i].end_node.index.get();
-            rate[from_index][to_index] = self.paths[i].weight;
-            next[from_index][to_index] = Some(to_index);
+    // Marks the nodes that are reachable from `from` and can also reach `to`
+    // without passing through a node that isn't `allowed` or taking a path that
+    // isn't in `usable_paths`.
+    fn nodes_between(
+        &self,
+        from: usize,
+        to: usize,
+        allowed: &[bool],
+        usable_paths: &[bool],
+    ) -> Vec<bool> {
+        let mut incoming = vec![vec![]; self.nodes.len()];
+        let mut outgoing = vec![vec![]; self.nodes.len()];
+        for (i, p) in self.paths.iter().enumerate() {
+            let (start, end) = (p.start_node.index, p.end_node.index);
+            if allowed[start] && allowed[end] && usable_paths[i] {
+                incoming[end].push(start);
+                outgoing[start].push(end);
+            }
         }
 
-        for k in 0..len {
-            for i in 0..len {
-                for j in 0..len {
-                    if rate[i][j] < rate[i][k] * rate[k][j] {
-                        rate[i][j] = rate[i][k] * rate[k][j];
-                        next[i][j] = next[i][k];
+        let reachable = |start: usize, edges: &[Vec<usize>]| {
+            let mut seen = vec![false; self.nodes.len()];
+            let mut queue = VecDeque::new();
+            seen[start] = true;
+            queue.push_back(start);
+            while let Some(n) = queue.pop_front() {
+                for &m in edges[n].iter() {
+                    if !seen[m] {
+                        seen[m] = true;
+                        queue.push_back(m);
                     }
                 }
             }
-        }
+            seen
+        };
+
+        let forward = reachable(from, &outgoing);
+        let backward = reachable(to, &incoming);
+        forward.iter().zip(backward).map(|(f, b)| *f && b).collect()
+    }
 
-        next
+    fn get_top_paths(&self, start_node: &Rc<Node>, end_node: &Rc<Node>) -> Vec<usize> {
+        self.all_pairs()
+            .route(start_node.index, end_node.index)
+            .unwrap_or_default()
     }
 
-    pub fn update(&mut self, request: &PriceUpdateRequest) {
-        let start_node = Rc::new(Node::new(&request.exchange, &request.source_currency, 0));
+    fn all_pairs(&self) -> RefMut<'_, AllPairs> {
+        RefMut::map(self.all_pairs.borrow_mut(), |cache| {
+            cache.get_or_insert_with(|| AllPairs::compute(self.nodes.len(), &self.paths))
+        })
+    }
 
-        let end_node = Rc::new(Node::new(
-            &request.exchange,
-            &request.destination_currency,
-            0,
-        ));
+    // Whether `update` would change anything: a new pair, a newer quote, or a
+    // late one for a time the history doesn't hold yet. A quote already held,
+    // current or past, is ignored.
+    pub fn would_accept(&self, request: &PriceUpdateRequest) -> bool {
+        let start = self.find_node_with(&request.exchange, &request.source_currency);
+        let end = self.find_node_with(&request.exchange, &request.destination_currency);
+        let path = start.zip(end).and_then(|(s, e)| {
+            self.find_path(s.index, e.index)
+                .or_else(|| self.find_path(e.index, s.index))
+        });
+        match path {
+            Some(p) => !self.paths[p]
+                .history
+                .iter()
+                .any(|v| v.timestamp == request.timestamp),
+            None => true,
+        }
+    }
 
-        let existing_paths: Vec<(Factor, &mut Path)> = self
-            .paths
-            .iter_mut()
-            .filter_map(|p| {
-                let mut t: Option<(Factor, &mut Path)> = None;
-                if p.start_node.eq(&start_node) && p.end_node.eq(&end_node) {
-                    t = Some((Factor::Forward, p));
-                } else if p.start_node.eq(&end_node) && p.end_node.eq(&start_node) {
-                    t = Some((Factor::Backward, p));
-                }
-                t
-            })
+    pub fn update(&mut self, request: &PriceUpdateRequest) -> UpdateOutcome {
+        let (start_node, is_new_start_node) =
+            self.find_or_insert_node(&request.exchange, &request.source_currency);
+        let (end_node, is_new_end_node) =
+            self.find_or_insert_node(&request.exchange, &request.destination_currency);
+
+        let forward_path = self.find_path(start_node.index, end_node.index);
+        let backward_path = self.find_path(end_node.index, start_node.index);
+        let existing_paths: Vec<(usize, f64, Option<Decimal>)> = forward_path
+            .map(|p| (p, request.forward_factor, request.exact_forward_factor))
+            .into_iter()
+            .chain(
+                backward_path.map(|p| (p, request.backward_factor, request.exact_backward_factor)),
+            )
             .collect();
 
-        if !existing_paths.is_empty() {
+        if let Some(&(first, _, _)) = existing_paths.first() {
             // update existing paths
-            for p in existing_paths {
-                if p.0 == Factor::Forward && request.timestamp > p.1.timestamp {
-                    p.1.timestamp = request.timestamp;
-                    p.1.weight = request.forward_factor;
-                }
-                if p.0 == Factor::Backward && request.timestamp > p.1.timestamp {
-                    p.1.timestamp = request.timestamp;
-                    p.1.weight = request.backward_factor;
+            let outcome = match request.timestamp.cmp(&self.paths[first].timestamp) {
+                Ordering::Greater => UpdateOutcome::Updated,
+                Ordering::Equal => UpdateOutcome::IgnoredDuplicate,
+                Ordering::Less => UpdateOutcome::IgnoredStale,
+            };
+            let fee = self.trading_fees.fee(
+                &request.exchange,
+                &request.source_currency,
+                &request.destination_currency,
+            );
+            let mut weight_changes = vec![];
+            for (index, weight, exact_weight) in existing_paths {
+                let p = &mut self.paths[index];
+                p.record(PathVersion::new(request.timestamp, weight, exact_weight));
+                if request.timestamp > p.timestamp {
+                    let old_weight = p.weight;
+                    p.timestamp = request.timestamp;
+                    p.gross_weight = weight;
+                    p.exact_gross_weight = exact_weight;
+                    p.apply_fee(fee);
+                    if p.weight != old_weight {
+                        weight_changes.push((
+                            p.start_node.index,
+                            p.end_node.index,
+                            old_weight,
+                            p.weight,
+                        ));
+                    }
                 }
             }
+            self.propagate_weight_changes(&weight_changes);
+            outcome
         } else {
             // insert new paths
-            self.paths.push(Path::new(
+            let fee = self.trading_fees.fee(
+                &request.exchange,
+                &request.source_currency,
+                &request.destination_currency,
+            );
+            let first_new_path = self.paths.len();
+            let mut forward = Path::new(
                 Rc::clone(&start_node),
                 Rc::clone(&end_node),
                 request.forward_factor,
+                request.exact_forward_factor,
                 request.timestamp,
                 Factor::Forward,
-            ));
+            );
+            forward.apply_fee(fee);
+            self.insert_path(forward);
 
-            self.paths.push(Path::new(
+            let mut backward = Path::new(
                 Rc::clone(&end_node),
                 Rc::clone(&start_node),
                 request.backward_factor,
+                request.exact_backward_factor,
                 request.timestamp,
                 Factor::Backward,
-            ));
-
-            // create new paths with weight 1.0 if new exchange
-            self.insert_additional_paths(
-                Rc::clone(&start_node),
-                Rc::clone(&end_node),
-                request.timestamp,
             );
+            backward.apply_fee(fee);
+            self.insert_path(backward);
+
+            // link new nodes to the same currency on the other exchanges
+            if is_new_start_node {
+                self.insert_additional_paths(&start_node, request.timestamp);
+            }
+            if is_new_end_node {
+                self.insert_additional_paths(&end_node, request.timestamp);
+            }
+
+            // two paths per link, after the forward and backward ones
+            let links = (self.paths.len() - first_new_path - 2) / 2;
+            self.propagate_new_paths(first_new_path);
+            UpdateOutcome::Inserted(links)
         }
+    }
 
-        // Insert if new node
-        self.insert_node(&start_node);
-        self.insert_node(&end_node);
+    fn propagate_weight_changes(&mut self, changes: &[(usize, usize, f64, f64)]) {
+        let paths = &self.paths;
+        let consistent = match self.all_pairs.get_mut() {
+            Some(all_pairs) => {
+                !all_pairs.has_negative_cycle()
+                    && changes.iter().all(|&(from, to, old, new)| {
+                        all_pairs.update_edge(from, to, old, new, paths)
+                    })
+            }
+            None => return,
+        };
+        if !consistent {
+            self.invalidate_cache();
+        }
     }
 
-    fn insert_additional_paths(
-        &mut self,
-        start_node: Rc<Node>,
-        end_node: Rc<Node>,
-        ts: DateTime<Utc>,
-    ) {
-        for existing_node in self.nodes.iter() {
-            if !self.nodes.contains(&start_node)
-                && existing_node.currency == start_node.currency
-                && existing_node.exchange != start_node.exchange
-            {
-                self.paths.push(Path::new(
-                    Rc::clone(existing_node),
-                    Rc::clone(&start_node),
-                    1.0,
-                    ts,
-                    Factor::FilledUpForward,
-                ));
-                self.paths.push(Path::new(
-                    Rc::clone(&start_node),
-                    Rc::clone(existing_node),
-                    1.0,
-                    ts,
-                    Factor::FilledUpBackward,
-                ));
-            } else if !self.nodes.contains(&end_node)
-                && existing_node.currency == end_node.currency
-                && existing_node.exchange != end_node.exchange
-            {
-                self.paths.push(Path::new(
-                    Rc::clone(existing_node),
-                    Rc::clone(&end_node),
-                    1.0,
-                    ts,
-                    Factor::FilledUpForward,
-                ));
-                self.paths.push(Path::new(
-                    Rc::clone(&end_node),
-                    Rc::clone(existing_node),
-                    1.0,
-                    ts,
-                    Factor::FilledUpBackward,
-                ));
+    fn propagate_new_paths(&mut self, first_new_path: usize) {
+        let node_count = self.nodes.len();
+        let paths = &self.paths[first_new_path..];
+        let consistent = match self.all_pairs.get_mut() {
+            Some(all_pairs) if all_pairs.has_negative_cycle() => false,
+            Some(all_pairs) => {
+                while all_pairs.node_count() < node_count {
+                    all_pairs.add_node();
+                }
+                paths
+                    .iter()
+                    .all(|p| all_pairs.insert_edge(p.start_node.index, p.end_node.index, p.weight))
             }
+            None => return,
+        };
+        if !consistent {
+            self.invalidate_cache();
         }
     }
 
-    fn insert_node(&mut self, n: &Rc<Node>) {
-        if !self.nodes.contains(n) {
-            n.index.set(self.nodes.len());
-            self.nodes.push(Rc::clone(n));
+    fn insert_additional_paths(&mut self, new_node: &Rc<Node>, ts: DateTime<Utc>) {
+        let same_currency_nodes: Vec<Rc<Node>> = self
+            .node_indices
+            .iter()
+            .filter(|(exchange, _)| **exchange != new_node.exchange)
+            .filter(|(exchange, _)| {
+                self.links
+                    .allows(&new_node.currency, exchange, &new_node.exchange)
+            })
+            .filter_map(|(_, currencies)| currencies.get(&new_node.currency))
+            .map(|&i| Rc::clone(&self.nodes[i]))
+            .collect();
+
+        for existing_node in same_currency_nodes.iter() {
+            self.insert_transfer_paths(existing_node, new_node, ts);
         }
     }
 
-    fn find_node_with<'a>(&self, exg: &'a str, curr: &'a str) -> Option<Rc<Node>> {
-        self.nodes
+    fn insert_transfer_paths(&mut self, from: &Rc<Node>, to: &Rc<Node>, ts: DateTime<Utc>) {
+        let (weight, exact_weight) =
+            self.transfer_costs
+                .weight(&to.currency, &from.exchange, &to.exchange);
+        self.insert_path(Path::new(
+            Rc::clone(from),
+            Rc::clone(to),
+            weight,
+            exact_weight,
+            ts,
+            Factor::FilledUpForward,
+        ));
+        let (weight, exact_weight) =
+            self.transfer_costs
+                .weight(&to.currency, &to.exchange, &from.exchange);
+        self.insert_path(Path::new(
+            Rc::clone(to),
+            Rc::clone(from),
+            weight,
+            exact_weight,
+            ts,
+            Factor::FilledUpBackward,
+        ));
+    }
+
+    // Adds and removes filled-up paths until they match the link settings.
+    fn relink(&mut self) {
+        let mut by_currency: HashMap<&str, Vec<usize>> = HashMap::new();
+        for n in self.nodes.iter() {
+            by_currency.entry(&n.currency).or_default().push(n.index);
+        }
+
+        let mut stale = HashSet::new();
+        let mut missing = vec![];
+        for nodes in by_currency.values() {
+            for (i, &a) in nodes.iter().enumerate() {
+                for &b in nodes[i + 1..].iter() {
+                    let (node_a, node_b) = (&self.nodes[a], &self.nodes[b]);
+                    let allowed =
+                        self.links
+                            .allows(&node_a.currency, &node_a.exchange, &node_b.exchange);
+                    let existing: Vec<usize> = self
+                        .find_path(a, b)
+                        .into_iter()
+                        .chain(self.find_path(b, a))
+                        .collect();
+                    if !allowed {
+                        stale.extend(existing);
+                    } else if existing.is_empty() {
+                        missing.push((a, b));
+                    }
+                }
+            }
+        }
+
+        self.remove_paths(&stale);
+        let first_new_path = self.paths.len();
+        for (a, b) in missing {
+            let (node_a, node_b) = (Rc::clone(&self.nodes[a]), Rc::clone(&self.nodes[b]));
+            let ts = self.latest_timestamp(a).max(self.latest_timestamp(b));
+            self.insert_transfer_paths(&node_a, &node_b, ts);
+        }
+        self.propagate_new_paths(first_new_path);
+    }
+
+    // Timestamp of the newest path leaving `node`.
+    fn latest_timestamp(&self, node: usize) -> DateTime<Utc> {
+        self.adjacency[node]
+            .iter()
+            .map(|&p| self.paths[p].timestamp)
+            .max()
+            .unwrap_or_else(|| Utc.timestamp(0, 0))
+    }
+
+    fn remove_paths(&mut self, removed: &HashSet<usize>) {
+        if removed.is_empty() {
+            return;
+        }
+        let mut index = 0;
+        self.paths.retain(|_| {
+            index += 1;
+            !removed.contains(&(index - 1))
+        });
+        for outgoing in self.adjacency.iter_mut() {
+            outgoing.clear();
+        }
+        for (i, p) in self.paths.iter().enumerate() {
+            self.adjacency[p.start_node.index].push(i);
+        }
+        self.invalidate_cache();
+    }
+
+    // Removes the forward and backward paths of a pair, and its nodes if they
+    // have no other pair left.
+    pub fn remove_pair(&mut self, request: &PairRequest) -> Result<(), GraphError> {
+        let removed = self.find_pair_paths(request)?;
+        self.remove_quotes(&removed);
+        Ok(())
+    }
+
+    // Keeps routes off a pair, or lets them back on, without touching its quotes.
+    pub fn set_halted(&mut self, request: &PairRequest, halted: bool) -> Result<(), GraphError> {
+        for i in self.find_pair_paths(request)? {
+            self.paths[i].halted = halted;
+        }
+        Ok(())
+    }
+
+    // The forward and backward paths of a pair.
+    fn find_pair_paths(&self, request: &PairRequest) -> Result<HashSet<usize>, GraphError> {
+        let start = self.find_node_with(&request.exchange, &request.source_currency);
+        let end = self.find_node_with(&request.exchange, &request.destination_currency);
+        let (start, end) = start.zip(end).ok_or(GraphError::UnknownPair)?;
+        let paths: HashSet<usize> = self
+            .find_path(start.index, end.index)
+            .into_iter()
+            .chain(self.find_path(end.index, start.index))
+            .collect();
+        if paths.is_empty() {
+            return Err(GraphError::UnknownPair);
+        }
+        Ok(paths)
+    }
+
+    // Removes every pair of an exchange, along with its nodes.
+    pub fn remove_exchange(&mut self, exchange: &str) -> Result<(), GraphError> {
+        if !self.node_indices.contains_key(exchange) {
+            return Err(GraphError::UnknownExchange);
+        }
+        let removed: HashSet<usize> = self
+            .paths
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_trade() && p.start_node.exchange == exchange)
+            .map(|(i, _)| i)
+            .collect();
+        self.remove_quotes(&removed);
+        Ok(())
+    }
+
+    // Removes trade paths, then the nodes no quote touches any more along with
+    // their filled-up paths. The nodes left keep their ids but are renumbered.
+    fn remove_quotes(&mut self, removed: &HashSet<usize>) {
+        let mut quoted = vec![false; self.nodes.len()];
+        for (_, p) in self
+            .paths
+            .iter()
+            .enumerate()
+            .filter(|(i, p)| p.is_trade() && !removed.contains(i))
+        {
+            quoted[p.start_node.index] = true;
+            quoted[p.end_node.index] = true;
+        }
+
+        let mut nodes: Vec<Rc<Node>> = vec![];
+        let mut new_indices = vec![0; self.nodes.len()];
+        for n in self.nodes.iter().filter(|n| quoted[n.index]) {
+            new_indices[n.index] = nodes.len();
+            nodes.push(Rc::new(Node::new(
+                &n.exchange,
+                &n.currency,
+                n.id,
+                nodes.len(),
+            )));
+        }
+        let paths: Vec<Path> = self
+            .paths
+            .drain(..)
+            .enumerate()
+            .filter(|(i, p)| {
+                !removed.contains(i) && quoted[p.start_node.index] && quoted[p.end_node.index]
+            })
+            .map(|(_, p)| Path {
+                start_node: Rc::clone(&nodes[new_indices[p.start_node.index]]),
+                end_node: Rc::clone(&nodes[new_indices[p.end_node.index]]),
+                ..p
+            })
+            .collect();
+
+        self.node_indices.clear();
+        for n in nodes.iter() {
+            self.node_indices
+                .entry(n.exchange.clone())
+                .or_default()
+                .insert(n.currency.clone(), n.index);
+        }
+        self.adjacency = vec![vec![]; nodes.len()];
+        for (i, p) in paths.iter().enumerate() {
+            self.adjacency[p.start_node.index].push(i);
+        }
+        self.nodes = nodes;
+        self.paths = paths;
+        self.invalidate_cache();
+    }
+
+    fn insert_path(&mut self, path: Path) {
+        self.adjacency[path.start_node.index].push(self.paths.len());
+        self.paths.push(path);
+    }
+
+    fn find_path(&self, from: usize, to: usize) -> Option<usize> {
+        self.adjacency[from]
             .iter()
-            .find(|&n| n.exchange == exg && n.currency == curr)
-            .and_then(|m| Some(Rc::clone(m)))
+            .cloned()
+            .find(|&p| self.paths[p].end_node.index == to)
+    }
+
+    fn find_or_insert_node(&mut self, exg: &str, curr: &str) -> (Rc<Node>, bool) {
+        match self.find_node_with(exg, curr) {
+            Some(n) => (n, false),
+            None => {
+                let index = self.nodes.len();
+                let n = Rc::new(Node::new(exg, curr, self.next_node_id, index));
+                self.next_node_id += 1;
+                self.nodes.push(Rc::clone(&n));
+                self.adjacency.push(vec![]);
+                self.node_indices
+                    .entry(exg.to_owned())
+                    .or_default()
+                    .insert(curr.to_owned(), index);
+                (n, true)
+            }
+        }
+    }
+
+    fn find_node_with(&self, exg: &str, curr: &str) -> Option<Rc<Node>> {
+        self.node_indices
+            .get(exg)
+            .and_then(|currencies| currencies.get(curr))
+            .map(|&i| Rc::clone(&self.nodes[i]))
     }
 
     pub fn clear(&mut self) {
+        if !self.paths.is_empty() {
+            self.invalidate_cache();
+        }
         self.nodes.clear();
+        self.node_indices.clear();
         self.paths.clear();
+        self.adjacency.clear();
+    }
+
+    fn invalidate_cache(&mut self) {
+        *self.all_pairs.get_mut() = None;
     }
 
     pub fn get_nodes(&self) -> Vec<Node> {
         self.nodes
             .iter()
-            .map(|x| (&**x).clone())
+            .map(|x| (**x).clone())
             .collect::<Vec<Node>>()
     }
 
@@ -266,18 +1146,67 @@ impl Graph {
         self.paths.to_vec()
     }
 
-    pub fn get_forward_backward_factor_of_existing_paths(&self) -> (Vec<f32>, Vec<f32>) {
-        let mut fnum = vec![];
-        let mut bnum = vec![];
-        for p in self.paths.iter() {
-            if p.factor_type == Factor::Forward {
-                fnum.push(p.weight);
-            } else if p.factor_type == Factor::Backward {
-                bnum.push(p.weight);
-            }
+    pub fn to_snapshot(&self) -> Snapshot {
+        Snapshot {
+            decimal_mode: self.decimal_mode,
+            max_age: self.max_age,
+            auto_link: self.links.auto_link(),
+            trading_fees: self.trading_fees.to_requests(),
+            links: self.links.to_requests(),
+            transfer_costs: self.transfer_costs.to_requests(),
+            next_node_id: self.next_node_id,
+            nodes: self.nodes.clone(),
+            paths: self.paths.clone(),
+        }
+    }
+
+    // Rebuilds the graph a snapshot was taken of. Weights are restored as they
+    // were saved rather than worked out again from the configuration.
+    pub fn from_snapshot(snapshot: Snapshot) -> Graph {
+        let mut graph = Graph::new();
+        graph.decimal_mode = snapshot.decimal_mode;
+        graph.max_age = snapshot.max_age;
+        graph.links.set_auto_link(snapshot.auto_link);
+        for l in snapshot.links.iter() {
+            graph.links.set(l);
+        }
+        for f in snapshot.trading_fees.iter() {
+            graph.trading_fees.set(f);
+        }
+        for c in snapshot.transfer_costs.iter() {
+            graph.transfer_costs.set(c);
+        }
+        graph.next_node_id = snapshot.next_node_id;
+        for n in snapshot.nodes.iter() {
+            graph
+                .node_indices
+                .entry(n.exchange.clone())
+                .or_default()
+                .insert(n.currency.clone(), n.index);
         }
-        (fnum, bnum)
+        graph.adjacency = vec![vec![]; snapshot.nodes.len()];
+        graph.nodes = snapshot.nodes;
+        for p in snapshot.paths {
+            graph.insert_path(p);
+        }
+        graph
+    }
+}
+
+// Node indices from `from` to `to` along a predecessor tree, both ends
+// included; `None` if `to` wasn't reached.
+fn route_from_pred(pred: &[Option<usize>], from: usize, to: usize) -> Option<Vec<usize>> {
+    let mut route = vec![to];
+    let mut current = to;
+    while current != from {
+        if route.len() > pred.len() {
+            return None;
+        }
+        current = pred[current]?;
+        route.push(current);
     }
+    route.reverse();
+    Some(route)
 }
 
 #[cfg(test)]
@@ -319,12 +1248,22 @@ mod test {
         " 0.0009 ",
     ];
 
+    fn hop(weight: &str, timestamp: &str) -> Hop {
+        Hop::new(
+            weight.parse().unwrap(),
+            Some(weight.parse().unwrap()),
+            DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
     #[test]
     fn with_correct_two_exchange_data() {
         let mut g = Graph::new();
-        let kraken = validate_price_update_input(&KRAKEN_PRICE, &g);
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
         g.update(&kraken.unwrap());
-        let gdax = validate_price_update_input(&GDAX_PRICE, &g);
+        let gdax = validate_price_update_input(GDAX_PRICE);
         g.update(&gdax.unwrap());
         let rate_req = ExchangeRateRequest::new(
             "KRAKEN".to_owned(),
@@ -337,6 +1276,17 @@ mod test {
 
         let expected = OptimalRateWithPath {
             rate: 1001.0,
+            exact_rate: None,
+            gross_rate: 1001.0,
+            exact_gross_rate: None,
+            oldest_edge_age: Some(chrono::Duration::zero()),
+            halted_rate: None,
+            hops: vec![
+                hop("1", "2017-11-01T09:42:23Z"),
+                hop("1001", "2017-11-01T09:42:23Z"),
+            ],
+            arbitrage_cycle: None,
+            approximate: false,
             paths: {
                 let mut vd = VecDeque::new();
                 vd.push_back(ExchangeCurrencyPair::new(
@@ -361,11 +1311,11 @@ mod test {
     #[test]
     fn with_correct_three_exchange_data() {
         let mut g = Graph::new();
-        let kraken = validate_price_update_input(&KRAKEN_PRICE, &g);
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
         g.update(&kraken.unwrap());
-        let gdax = validate_price_update_input(&GDAX_PRICE, &g);
+        let gdax = validate_price_update_input(GDAX_PRICE);
         g.update(&gdax.unwrap());
-        let bittrex = validate_price_update_input(&BITTREX_PRICE, &g);
+        let bittrex = validate_price_update_input(BITTREX_PRICE);
         g.update(&bittrex.unwrap());
         let rate_req = ExchangeRateRequest::new(
             "KRAKEN".to_owned(),
@@ -378,6 +1328,18 @@ mod test {
 
         let expected = OptimalRateWithPath {
             rate: 1002.0,
+            exact_rate: None,
+            gross_rate: 1002.0,
+            exact_gross_rate: None,
+            oldest_edge_age: Some(chrono::Duration::zero()),
+            halted_rate: None,
+            hops: vec![
+                hop("1", "2017-11-01T09:42:23Z"),
+                hop("1002", "2017-11-01T09:42:23Z"),
+                hop("1", "2017-11-01T09:42:23Z"),
+            ],
+            arbitrage_cycle: None,
+            approximate: false,
             paths: {
                 let mut vd = VecDeque::new();
                 vd.push_back(ExchangeCurrencyPair::new(
@@ -406,11 +1368,11 @@ mod test {
     #[test]
     fn with_same_exchange_but_updated_data() {
         let mut g = Graph::new();
-        let kraken = validate_price_update_input(&KRAKEN_PRICE, &g);
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
         g.update(&kraken.unwrap());
-        let kraken_update = validate_price_update_input(&KRAKEN_PRICE_WITH_LATEST_DATE, &g);
+        let kraken_update = validate_price_update_input(KRAKEN_PRICE_WITH_LATEST_DATE);
         g.update(&kraken_update.unwrap());
-        let gdax = validate_price_update_input(&GDAX_PRICE, &g);
+        let gdax = validate_price_update_input(GDAX_PRICE);
         g.update(&gdax.unwrap());
         let rate_req = ExchangeRateRequest::new(
             "KRAKEN".to_owned(),
@@ -423,6 +1385,17 @@ mod test {
 
         let expected = OptimalRateWithPath {
             rate: 1018.0,
+            exact_rate: None,
+            gross_rate: 1018.0,
+            exact_gross_rate: None,
+            oldest_edge_age: Some(chrono::Duration::zero()),
+            halted_rate: None,
+            hops: vec![
+                hop("1018", "2018-11-01T09:42:23Z"),
+                hop("1", "2017-11-01T09:42:23Z"),
+            ],
+            arbitrage_cycle: None,
+            approximate: false,
             paths: {
                 let mut vd = VecDeque::new();
                 vd.push_back(ExchangeCurrencyPair::new(
@@ -444,10 +1417,1245 @@ mod test {
         assert_eq!(expected, result.unwrap());
     }
 
+    #[test]
+    fn should_keep_cached_result_across_price_updates() {
+        let mut g = Graph::new();
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+
+        assert!(g.all_pairs.borrow().is_none());
+        assert_eq!(1001.0, g.get_top_result(&rate_req).unwrap().rate);
+        assert!(g.all_pairs.borrow().is_some());
+
+        let kraken_update = validate_price_update_input(KRAKEN_PRICE_WITH_LATEST_DATE);
+        g.update(&kraken_update.unwrap());
+        assert!(g.all_pairs.borrow().is_some());
+        assert_eq!(1018.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        g.clear();
+        assert!(g.all_pairs.borrow().is_none());
+    }
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn spread(&mut self) -> f64 {
+            0.001 + (self.next() % 1000) as f64 / 20_000.0
+        }
+    }
+
+    #[test]
+    fn incremental_update_should_match_full_recomputation() {
+        const EXCHANGES: &[&str] = &["KRAKEN", "GDAX", "BITTREX", "BINANCE"];
+        // quotes are derived from a consistent price per currency, so no cycle gains
+        const CURRENCIES: &[(&str, f64)] = &[
+            ("BTC", 6400.0),
+            ("ETH", 290.0),
+            ("USD", 1.0),
+            ("EUR", 1.15),
+            ("LTC", 52.0),
+        ];
+
+        for seed in 1..=20 {
+            let mut rng = XorShift(0x9E37_79B9_7F4A_7C15 ^ seed);
+            let mut g = Graph::new();
+            let base = Utc.ymd(2017, 11, 1).and_hms(9, 0, 0);
+
+            for step in 0..150 {
+                let exchange = EXCHANGES[rng.below(EXCHANGES.len())];
+                let source = CURRENCIES[rng.below(CURRENCIES.len())];
+                let destination = CURRENCIES[rng.below(CURRENCIES.len())];
+                if source.0 == destination.0 {
+                    continue;
+                }
+                // roughly one tick in five arrives out of order
+                let offset = step as i64 - if rng.below(5) == 0 { 10 } else { 0 };
+                g.update(&PriceUpdateRequest::new(
+                    base + chrono::Duration::seconds(offset),
+                    exchange.to_owned(),
+                    source.0.to_owned(),
+                    destination.0.to_owned(),
+                    source.1 / destination.1 * (1.0 - rng.spread()),
+                    destination.1 / source.1 * (1.0 - rng.spread()),
+                ));
+
+                if let Some(incremental) = g.all_pairs.borrow().as_ref() {
+                    let full = AllPairs::compute(g.nodes.len(), &g.paths);
+                    assert_eq!(full.node_count(), incremental.node_count());
+                    for i in 0..full.node_count() {
+                        for j in 0..full.node_count() {
+                            let (expected, actual) = (full.cost(i, j), incremental.cost(i, j));
+                            assert!(
+                                expected == actual || (expected - actual).abs() <= 1e-9,
+                                "seed {} step {}: cost[{}][{}] {} != {}",
+                                seed,
+                                step,
+                                i,
+                                j,
+                                actual,
+                                expected
+                            );
+                            assert_eq!(expected.is_finite(), incremental.route(i, j).is_some());
+                        }
+                    }
+                }
+
+                // make sure the next update is applied to a populated cache
+                let first = Rc::clone(&g.nodes[0]);
+                g.get_top_paths(&first, &first);
+            }
+        }
+    }
+
+    fn chain(g: &mut Graph, exchange: &str, factors: &[(f64, f64)]) -> ExchangeRateRequest {
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 42, 23);
+        for (i, &(forward, backward)) in factors.iter().enumerate() {
+            g.update(&PriceUpdateRequest::new(
+                ts,
+                exchange.to_owned(),
+                format!("C{}", i),
+                format!("C{}", i + 1),
+                forward,
+                backward,
+            ));
+        }
+        ExchangeRateRequest::new(
+            exchange.to_owned(),
+            "C0".to_owned(),
+            exchange.to_owned(),
+            format!("C{}", factors.len()),
+        )
+    }
+
+    #[test]
+    fn should_keep_twelve_significant_digits_over_long_chains() {
+        let mut g = Graph::new();
+        let factors = [(0.0009, 1000.0), (1000.0, 0.0009)].repeat(6);
+        let rate_req = chain(&mut g, "KRAKEN", &factors);
+
+        let result = g.get_top_result(&rate_req).unwrap();
+
+        let route: Vec<String> = result.paths.iter().map(|p| p.currency.clone()).collect();
+        let expected_route: Vec<String> = (0..=factors.len()).map(|i| format!("C{}", i)).collect();
+        assert_eq!(expected_route, route);
+
+        let expected = 0.531441; // 0.9 ^ 6
+        let f32_rate = factors.iter().fold(1_f32, |r, f| r * f.0 as f32);
+        assert!((result.rate - expected).abs() < expected * 1e-12);
+        assert!((f64::from(f32_rate) - expected).abs() > (result.rate - expected).abs());
+    }
+
+    #[test]
+    fn should_not_overflow_on_long_chains_of_big_factors() {
+        let mut g = Graph::new();
+        let factors = [(1e6, 1e-6)].repeat(8);
+        let rate_req = chain(&mut g, "KRAKEN", &factors);
+
+        let result = g.get_top_result(&rate_req).unwrap();
+
+        let f32_rate = factors.iter().fold(1_f32, |r, f| r * f.0 as f32);
+        assert!(f32_rate.is_infinite());
+        assert!((result.rate - 1e48).abs() < 1e48 * 1e-12);
+        assert_eq!(factors.len() + 1, result.paths.len());
+    }
+
+    #[test]
+    fn should_compute_exact_decimal_rates() {
+        let mut g = Graph::new();
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        g.set_decimal_mode(Some(DecimalMode::new(4, Rounding::HalfEven)));
+        let rate_req = ExchangeRateRequest::new(
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+        );
+
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("0.0009", result.exact_rate.unwrap().to_string());
+
+        // 0.1 ^ 3 drifts in binary floating point but not in decimal mode
+        let mut g = Graph::new();
+        let rate_req = chain(&mut g, "KRAKEN", &[(0.1, 9.0), (0.1, 9.0), (0.1, 9.0)]);
+        g.set_decimal_mode(Some(DecimalMode::new(20, Rounding::HalfUp)));
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_ne!(0.001, result.rate);
+        assert_eq!(
+            "0.00100000000000000000",
+            result.exact_rate.unwrap().to_string()
+        );
+
+        g.set_decimal_mode(Some(DecimalMode::new(2, Rounding::Up)));
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("0.01", result.exact_rate.unwrap().to_string());
+
+        g.set_decimal_mode(None);
+        assert_eq!(None, g.get_top_result(&rate_req).unwrap().exact_rate);
+    }
+
+    #[test]
+    fn should_find_triangular_arbitrage() {
+        let mut g = Graph::new();
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        assert!(g.get_arbitrage_cycles().is_empty());
+
+        // BTC -> USD -> ETH -> BTC on KRAKEN gains 1000 * 0.01 * 0.11 = 1.1
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 43, 0);
+        for &(source, destination, forward, backward) in
+            [("ETH", "USD", 99.0, 0.01), ("ETH", "BTC", 0.11, 9.0)].iter()
+        {
+            g.update(&PriceUpdateRequest::new(
+                ts,
+                "KRAKEN".to_owned(),
+                source.to_owned(),
+                destination.to_owned(),
+                forward,
+                backward,
+            ));
+        }
+
+        let cycles = g.get_arbitrage_cycles();
+        assert_eq!(1, cycles.len());
+        assert!((cycles[0].gain - 1.1).abs() < 1e-12);
+        let route: Vec<(&str, &str)> = cycles[0]
+            .paths
+            .iter()
+            .map(|p| (p.exchange.as_str(), p.currency.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("KRAKEN", "BTC"),
+                ("KRAKEN", "USD"),
+                ("KRAKEN", "ETH"),
+                ("KRAKEN", "BTC")
+            ],
+            route
+        );
+    }
+
+    #[test]
+    fn should_flag_unbounded_rate_and_keep_best_simple_path() {
+        let mut g = Graph::new();
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 43, 0);
+        for &(exchange, source, destination, forward, backward) in [
+            ("KRAKEN", "ETH", "USD", 99.0, 0.01),
+            ("KRAKEN", "ETH", "BTC", 0.11, 9.0),
+            ("BITTREX", "LTC", "EUR", 48.0, 0.02),
+        ]
+        .iter()
+        {
+            g.update(&PriceUpdateRequest::new(
+                ts,
+                exchange.to_owned(),
+                source.to_owned(),
+                destination.to_owned(),
+                forward,
+                backward,
+            ));
+        }
+
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "KRAKEN".to_owned(),
+            "ETH".to_owned(),
+        );
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert!(!result.approximate);
+        let cycle = result.arbitrage_cycle.unwrap();
+        assert!((cycle.gain - 1.1).abs() < 1e-12);
+        // KRAKEN BTC -> GDAX BTC -> GDAX USD -> KRAKEN USD -> KRAKEN ETH
+        assert!((result.rate - 1001.0 * 0.01).abs() < 1e-12);
+        let route: Vec<(&str, &str)> = result
+            .paths
+            .iter()
+            .map(|p| (p.exchange.as_str(), p.currency.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("KRAKEN", "BTC"),
+                ("GDAX", "BTC"),
+                ("GDAX", "USD"),
+                ("KRAKEN", "USD"),
+                ("KRAKEN", "ETH")
+            ],
+            route
+        );
+
+        // a hop limit bounds the rate again
+        let result = g.get_top_result(&rate_req.with_max_hops(Some(1))).unwrap();
+        assert_eq!(None, result.arbitrage_cycle);
+        assert_eq!(9.0, result.rate);
+
+        // pairs that can't reach the cycle keep their ordinary best rate
+        let rate_req = ExchangeRateRequest::new(
+            "BITTREX".to_owned(),
+            "LTC".to_owned(),
+            "BITTREX".to_owned(),
+            "EUR".to_owned(),
+        );
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(None, result.arbitrage_cycle);
+        assert_eq!(48.0, result.rate);
+    }
+
+    #[test]
+    fn should_mark_the_route_approximate_when_the_search_is_cut_short() {
+        // every pair of eleven currencies is quoted and C0 -> C1 -> C0 gains, so
+        // the simple routes from C0 to C10 are far more than the search may visit
+        let mut g = Graph::new();
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 43, 0);
+        for i in 0..11 {
+            for j in i + 1..11 {
+                let forward = if (i, j) == (0, 1) { 1.1 } else { 1.0 };
+                g.update(&PriceUpdateRequest::new(
+                    ts,
+                    "KRAKEN".to_owned(),
+                    format!("C{}", i),
+                    format!("C{}", j),
+                    forward,
+                    1.0,
+                ));
+            }
+        }
+
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "C0".to_owned(),
+            "KRAKEN".to_owned(),
+            "C10".to_owned(),
+        );
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert!(result.arbitrage_cycle.is_some());
+        assert!(result.approximate);
+        assert!((result.rate - 1.1).abs() < 1e-12);
+
+        // a hop limit needs no search, so the rate is exact again
+        let result = g.get_top_result(&rate_req.with_max_hops(Some(2))).unwrap();
+        assert!(!result.approximate);
+    }
+
+    #[test]
+    fn should_rank_the_best_loop_free_routes() {
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, BITTREX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+
+        let single = g.get_top_results(&rate_req).unwrap();
+        assert_eq!(vec![g.get_top_result(&rate_req).unwrap()], single);
+
+        // four routes sell on BITTREX, reaching it directly or through GDAX and
+        // then moving the USD directly or through KRAKEN; two more sell on GDAX
+        let results = g.get_top_results(&rate_req.with_route_count(5)).unwrap();
+        let rates: Vec<f64> = results.iter().map(|r| r.rate).collect();
+        assert_eq!(vec![1002.0, 1002.0, 1002.0, 1002.0, 1001.0], rates);
+        assert_eq!(single[0], results[0]);
+        for (i, result) in results.iter().enumerate() {
+            assert!(results[..i].iter().all(|r| r.paths != result.paths));
+            // loop-free: no node is visited twice
+            let mut visited: Vec<(&str, &str)> = result
+                .paths
+                .iter()
+                .map(|p| (p.exchange.as_str(), p.currency.as_str()))
+                .collect();
+            visited.sort();
+            visited.dedup();
+            assert_eq!(result.paths.len(), visited.len());
+        }
+
+        // fewer routes exist than requested
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "KRAKEN".to_owned(),
+            "USD".to_owned(),
+        );
+        let results = g.get_top_results(&rate_req.with_route_count(1000)).unwrap();
+        assert!(results.len() < 1000);
+        assert!(results.windows(2).all(|w| w[0].rate >= w[1].rate));
+    }
+
+    #[test]
+    fn should_respect_the_hop_limit() {
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, BITTREX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let rate_req = || {
+            ExchangeRateRequest::new(
+                "KRAKEN".to_owned(),
+                "BTC".to_owned(),
+                "GDAX".to_owned(),
+                "USD".to_owned(),
+            )
+        };
+
+        let result = g.get_top_result(&rate_req()).unwrap();
+        assert_eq!(1002.0, result.rate);
+        assert_eq!(4, result.paths.len());
+
+        // the BITTREX route takes three hops, the GDAX one only two
+        let result = g
+            .get_top_result(&rate_req().with_max_hops(Some(2)))
+            .unwrap();
+        assert_eq!(1001.0, result.rate);
+        assert_eq!(3, result.paths.len());
+
+        let results = g
+            .get_top_results(&rate_req().with_max_hops(Some(2)).with_route_count(5))
+            .unwrap();
+        let rates: Vec<f64> = results.iter().map(|r| r.rate).collect();
+        assert_eq!(vec![1001.0, 1000.0], rates);
+
+        let result = g.get_top_result(&rate_req().with_max_hops(Some(1)));
+        assert_eq!(GraphError::PathNotFound, result.unwrap_err());
+    }
+
+    #[test]
+    fn should_route_only_through_allowed_exchanges() {
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, BITTREX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let rate_req = |excluded: &[&str], only: Option<&[&str]>| {
+            let to_owned = |exchanges: &[&str]| -> Vec<String> {
+                exchanges.iter().map(|e| (*e).to_owned()).collect()
+            };
+            ExchangeRateRequest::new(
+                "KRAKEN".to_owned(),
+                "BTC".to_owned(),
+                "GDAX".to_owned(),
+                "USD".to_owned(),
+            )
+            .with_exchange_filters(to_owned(excluded), only.map(to_owned))
+        };
+        let exchanges = |result: OptimalRateWithPath| -> Vec<String> {
+            result.paths.into_iter().map(|p| p.exchange).collect()
+        };
+
+        let result = g.get_top_result(&rate_req(&["BITTREX"], None)).unwrap();
+        assert_eq!(1001.0, result.rate);
+        assert_eq!(vec!["KRAKEN", "GDAX", "GDAX"], exchanges(result));
+
+        // the destination stays allowed, but no other GDAX node does
+        let result = g.get_top_result(&rate_req(&[], Some(&["KRAKEN"]))).unwrap();
+        assert_eq!(1000.0, result.rate);
+        assert_eq!(vec!["KRAKEN", "KRAKEN", "GDAX"], exchanges(result));
+
+        let result = g.get_top_result(&rate_req(&["GDAX"], None)).unwrap();
+        assert_eq!(1002.0, result.rate);
+        assert_eq!(
+            vec!["KRAKEN", "BITTREX", "BITTREX", "GDAX"],
+            exchanges(result)
+        );
+
+        let results = g
+            .get_top_results(
+                &rate_req(&["BITTREX"], Some(&["BITTREX", "GDAX"])).with_route_count(5),
+            )
+            .unwrap();
+        let rates: Vec<f64> = results.iter().map(|r| r.rate).collect();
+        assert_eq!(vec![1001.0], rates);
+    }
+
+    #[test]
+    fn should_charge_transfer_costs_on_filled_up_paths() {
+        let transfer = |from: &str, to: &str, proportional_fee: &str, flat_fee: &str| {
+            TransferCostRequest::new(
+                "BTC".to_owned(),
+                from.to_owned(),
+                to.to_owned(),
+                TransferCost::new(proportional_fee.parse().unwrap(), flat_fee.parse().unwrap())
+                    .unwrap(),
+            )
+        };
+        let mut g = Graph::new();
+        g.set_decimal_mode(Some(DecimalMode::new(8, Rounding::HalfEven)));
+        // known before the path exists
+        g.set_transfer_cost(&transfer("KRAKEN", "GDAX", "0", "0.0005"));
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("1000.49950000", result.exact_rate.unwrap().to_string());
+        assert_eq!("GDAX", result.paths[1].exchange);
+
+        // repricing the existing path makes selling on KRAKEN better
+        g.set_transfer_cost(&transfer("KRAKEN", "GDAX", "0.01", "0.0005"));
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1000.0, result.rate);
+        assert_eq!("KRAKEN", result.paths[1].exchange);
+
+        // the other direction is still free
+        let back = g.find_path(
+            g.find_node_with("GDAX", "BTC").unwrap().index,
+            g.find_node_with("KRAKEN", "BTC").unwrap().index,
+        );
+        assert_eq!(1.0, g.paths[back.unwrap()].weight);
+        assert_eq!(1, g.get_transfer_costs().len());
+    }
+
+    #[test]
+    fn should_report_exact_rates_of_long_routes_with_fees() {
+        let mut g = Graph::new();
+        g.set_decimal_mode(Some(DecimalMode::new(8, Rounding::HalfEven)));
+        g.set_trading_fee(&validate_trading_fee_input(&["KRAKEN", "0.0026"]).unwrap());
+        for (source, destination, forward, backward) in [
+            ("BTC", "USD", "6543.21098765", "0.00015282"),
+            ("USD", "EUR", "0.91234567", "1.09587654"),
+            ("EUR", "JPY", "162.34567891", "0.00615900"),
+        ] {
+            let update = validate_price_update_input(&[
+                "2017-11-01T09:42:23+00:00",
+                "KRAKEN",
+                source,
+                destination,
+                forward,
+                backward,
+            ]);
+            g.update(&update.unwrap());
+        }
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "KRAKEN".to_owned(),
+            "JPY".to_owned(),
+        );
+
+        // the unrounded product needs a 48-digit mantissa
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("961610.42956982", result.exact_rate.unwrap().to_string());
+    }
+
+    #[test]
+    fn should_apply_trading_fees_to_quotes() {
+        let fee = |args: &[&str]| validate_trading_fee_input(args).unwrap();
+        let mut g = Graph::new();
+        g.set_decimal_mode(Some(DecimalMode::new(6, Rounding::HalfEven)));
+        g.set_trading_fee(&fee(&["KRAKEN", "0.01"]));
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+        let assert_cache_is_consistent = |g: &Graph| {
+            let full = AllPairs::compute(g.nodes.len(), &g.paths);
+            let cached = g.all_pairs.borrow();
+            for i in 0..full.node_count() {
+                for j in 0..full.node_count() {
+                    let (expected, actual) = (full.cost(i, j), cached.as_ref().unwrap().cost(i, j));
+                    assert!(expected == actual || (expected - actual).abs() <= 1e-9);
+                }
+            }
+        };
+
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1001.0, result.rate);
+        assert_eq!(1001.0, result.gross_rate);
+
+        g.set_trading_fee(&fee(&["GDAX", "0.002"]));
+        assert_cache_is_consistent(&g);
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("998.998000", result.exact_rate.unwrap().to_string());
+        assert_eq!("1001.000000", result.exact_gross_rate.unwrap().to_string());
+
+        // the pair fee overrides the exchange fee, and selling on KRAKEN wins
+        g.set_trading_fee(&fee(&["GDAX", "USD", "BTC", "0.02"]));
+        assert_cache_is_consistent(&g);
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("990.000000", result.exact_rate.unwrap().to_string());
+        assert_eq!("1000.000000", result.exact_gross_rate.unwrap().to_string());
+
+        // newer quotes are charged the fee too
+        let kraken_update = validate_price_update_input(KRAKEN_PRICE_WITH_LATEST_DATE);
+        g.update(&kraken_update.unwrap());
+        assert_cache_is_consistent(&g);
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!("1007.820000", result.exact_rate.unwrap().to_string());
+        assert_eq!(3, g.get_trading_fees().len());
+    }
+
+    #[test]
+    fn should_link_exchanges_only_where_allowed() {
+        let link = |a: &str, b: &str, linked: bool| {
+            LinkRequest::new("BTC".to_owned(), (a.to_owned(), b.to_owned()), linked)
+        };
+        let mut g = Graph::new();
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
+        g.update(&kraken.unwrap());
+        let gdax = validate_price_update_input(GDAX_PRICE);
+        g.update(&gdax.unwrap());
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+        assert_eq!(1001.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        g.set_link(&link("GDAX", "KRAKEN", false));
+        assert_eq!(1000.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        // only hand-made links are left, and there are none yet
+        g.set_auto_link(false);
+        assert_eq!(
+            GraphError::PathNotFound,
+            g.get_top_result(&rate_req).unwrap_err()
+        );
+        assert!(g.paths.iter().all(|p| p.is_trade()));
+
+        g.set_link(&link("KRAKEN", "GDAX", true));
+        assert_eq!(1001.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        let bittrex = validate_price_update_input(BITTREX_PRICE);
+        g.update(&bittrex.unwrap());
+        let bittrex_btc = g.find_node_with("BITTREX", "BTC").unwrap();
+        assert_eq!(1, g.adjacency[bittrex_btc.index].len());
+        assert_eq!(1001.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        g.set_auto_link(true);
+        assert_eq!(1002.0, g.get_top_result(&rate_req).unwrap().rate);
+        let full = AllPairs::compute(g.nodes.len(), &g.paths);
+        let cached = g.all_pairs.borrow();
+        for i in 0..full.node_count() {
+            for j in 0..full.node_count() {
+                assert_eq!(full.cost(i, j), cached.as_ref().unwrap().cost(i, j));
+            }
+        }
+    }
+
+    #[test]
+    fn should_ignore_quotes_older_than_the_ttl() {
+        const OLD_BITTREX_PRICE: &[&str] = &[
+            "2017-11-01T09:00:00+00:00",
+            "BITTREX",
+            "BTC",
+            "USD",
+            "1002.0",
+            "0.0009",
+        ];
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, OLD_BITTREX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1002.0, result.rate);
+        assert_eq!(
+            Some(Duration::seconds(42 * 60 + 23)),
+            result.oldest_edge_age
+        );
+
+        g.set_max_age(Some(Duration::seconds(600)));
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1001.0, result.rate);
+        assert_eq!(Some(Duration::zero()), result.oldest_edge_age);
+        let results = g.get_top_results(&rate_req.with_route_count(10)).unwrap();
+        let rates: Vec<f64> = results.iter().map(|r| r.rate).collect();
+        // transfers through BITTREX don't go stale, its quotes do
+        assert_eq!(vec![1001.0, 1001.0, 1000.0, 1000.0], rates);
+
+        // a fresh quote brings BITTREX back
+        g.update(&PriceUpdateRequest::new(
+            Utc.ymd(2017, 11, 1).and_hms(9, 45, 0),
+            "BITTREX".to_owned(),
+            "BTC".to_owned(),
+            "USD".to_owned(),
+            1002.0,
+            0.0009,
+        ));
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1002.0, result.rate);
+        assert_eq!(Some(Duration::zero()), result.oldest_edge_age);
+
+        // the clock moved on, so the KRAKEN and GDAX quotes are getting old
+        g.set_max_age(Some(Duration::seconds(60)));
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "KRAKEN".to_owned(),
+            "USD".to_owned(),
+        );
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1002.0, result.rate);
+        assert_eq!(Some(Duration::zero()), result.oldest_edge_age);
+    }
+
+    #[test]
+    fn should_answer_with_the_quotes_known_at_a_time() {
+        const LATE_KRAKEN_PRICE: &[&str] = &[
+            "2017-11-01T09:50:00+00:00",
+            "KRAKEN",
+            "BTC",
+            "USD",
+            "1005.0",
+            "0.0009",
+        ];
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, KRAKEN_PRICE_WITH_LATEST_DATE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        // arrives out of order: too old for the current graph, kept in the history
+        assert!(g.would_accept(&validate_price_update_input(LATE_KRAKEN_PRICE).unwrap()));
+        g.update(&validate_price_update_input(LATE_KRAKEN_PRICE).unwrap());
+        let rate_req = || {
+            ExchangeRateRequest::new(
+                "KRAKEN".to_owned(),
+                "BTC".to_owned(),
+                "GDAX".to_owned(),
+                "USD".to_owned(),
+            )
+        };
+
+        assert_eq!(1018.0, g.get_top_result(&rate_req()).unwrap().rate);
+        let at = |h, m| Some(Utc.ymd(2017, 11, 1).and_hms(h, m, 0));
+        assert_eq!(
+            1001.0,
+            g.get_top_result(&rate_req().with_at(at(9, 45)))
+                .unwrap()
+                .rate
+        );
+        let result = g.get_top_result(&rate_req().with_at(at(10, 0))).unwrap();
+        assert_eq!(1005.0, result.rate);
+        assert_eq!(Some(Duration::minutes(10)), result.oldest_edge_age);
+        assert_eq!(
+            GraphError::PathNotFound,
+            g.get_top_result(&rate_req().with_at(at(9, 0))).unwrap_err()
+        );
+
+        // the TTL counts back from the requested time
+        g.set_max_age(Some(Duration::seconds(600)));
+        let result = g.get_top_result(&rate_req().with_at(at(9, 55))).unwrap();
+        assert_eq!(1005.0, result.rate);
+        assert_eq!(Some(Duration::minutes(5)), result.oldest_edge_age);
+        assert_eq!(
+            GraphError::PathNotFound,
+            g.get_top_result(&rate_req().with_at(at(10, 1)))
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_report_the_outcome_of_each_update() {
+        let mut g = Graph::new();
+        let update = |price| validate_price_update_input(price).unwrap();
+        assert!(g.would_accept(&update(KRAKEN_PRICE)));
+        assert_eq!(UpdateOutcome::Inserted(0), g.update(&update(KRAKEN_PRICE)));
+        // BTC and USD on GDAX both get linked to KRAKEN
+        assert_eq!(UpdateOutcome::Inserted(2), g.update(&update(GDAX_PRICE)));
+        assert!(!g.would_accept(&update(KRAKEN_PRICE)));
+        assert!(g.would_accept(&update(KRAKEN_PRICE_WITH_LATEST_DATE)));
+        assert_eq!(
+            UpdateOutcome::IgnoredDuplicate,
+            g.update(&update(KRAKEN_PRICE))
+        );
+        assert_eq!(
+            UpdateOutcome::Updated,
+            g.update(&update(KRAKEN_PRICE_WITH_LATEST_DATE))
+        );
+        assert!(!g.would_accept(&update(KRAKEN_PRICE)));
+        assert_eq!(UpdateOutcome::IgnoredStale, g.update(&update(KRAKEN_PRICE)));
+        // a reversed pair updates the same paths
+        assert_eq!(
+            UpdateOutcome::Updated,
+            g.update(&PriceUpdateRequest::new(
+                Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+                "KRAKEN".to_owned(),
+                "USD".to_owned(),
+                "BTC".to_owned(),
+                0.0009,
+                1100.0,
+            ))
+        );
+    }
+
+    #[test]
+    fn should_remove_pairs_and_exchanges() {
+        const KRAKEN_EUR_PRICE: &[&str] = &[
+            "2017-11-01T09:42:23+00:00",
+            "KRAKEN",
+            "BTC",
+            "EUR",
+            "900.0",
+            "0.0011",
+        ];
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, BITTREX_PRICE, KRAKEN_EUR_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+        assert_eq!(1002.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        // KRAKEN BTC still trades against EUR, KRAKEN USD is orphaned
+        let pair = PairRequest::new("KRAKEN".to_owned(), "USD".to_owned(), "BTC".to_owned());
+        g.remove_pair(&pair).unwrap();
+        assert_eq!(GraphError::UnknownPair, g.remove_pair(&pair).unwrap_err());
+        let nodes = g.get_nodes();
+        assert_eq!(6, nodes.len());
+        assert!(!nodes
+            .iter()
+            .any(|n| n.exchange == "KRAKEN" && n.currency == "USD"));
+        assert!(nodes.iter().enumerate().all(|(i, n)| n.index == i));
+        assert!(!g
+            .get_paths()
+            .iter()
+            .any(
+                |p| p.start_node.exchange == "KRAKEN" && p.start_node.currency == "USD"
+                    || p.end_node.exchange == "KRAKEN" && p.end_node.currency == "USD"
+            ));
+        assert_eq!(1002.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        let bittrex_ids: Vec<usize> = nodes
+            .iter()
+            .filter(|n| n.exchange == "BITTREX")
+            .map(|n| n.id)
+            .collect();
+        g.remove_exchange("BITTREX").unwrap();
+        assert_eq!(
+            GraphError::UnknownExchange,
+            g.remove_exchange("BITTREX").unwrap_err()
+        );
+        assert_eq!(1001.0, g.get_top_result(&rate_req).unwrap().rate);
+
+        // ids survive removals and are never handed out again
+        let ids: Vec<usize> = g.get_nodes().iter().map(|n| n.id).collect();
+        assert_eq!(vec![0, 2, 3, 6], ids);
+        g.update(&validate_price_update_input(BITTREX_PRICE).unwrap());
+        let new_ids: Vec<usize> = g
+            .get_nodes()
+            .iter()
+            .filter(|n| n.exchange == "BITTREX")
+            .map(|n| n.id)
+            .collect();
+        assert!(new_ids
+            .iter()
+            .all(|id| !bittrex_ids.contains(id) && *id > 6));
+        assert_eq!(1002.0, g.get_top_result(&rate_req).unwrap().rate);
+    }
+
+    #[test]
+    fn should_route_around_halted_pairs() {
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, BITTREX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+        let bittrex = PairRequest::new("BITTREX".to_owned(), "USD".to_owned(), "BTC".to_owned());
+        g.set_halted(&bittrex, true).unwrap();
+        assert_eq!(
+            2,
+            g.get_paths()
+                .iter()
+                .filter(|p| p.halted && p.start_node.exchange == "BITTREX")
+                .count()
+        );
+
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1001.0, result.rate);
+        assert_eq!(Some(1002.0), result.halted_rate);
+        // alternatives don't repeat the note
+        let results = g.get_top_results(&rate_req.with_route_count(2)).unwrap();
+        assert_eq!(None, results[1].halted_rate);
+
+        for exchange in ["KRAKEN", "GDAX"].iter() {
+            g.set_halted(
+                &PairRequest::new(exchange.to_string(), "BTC".to_owned(), "USD".to_owned()),
+                true,
+            )
+            .unwrap();
+        }
+        let rate_req = ExchangeRateRequest::new(
+            "KRAKEN".to_owned(),
+            "BTC".to_owned(),
+            "GDAX".to_owned(),
+            "USD".to_owned(),
+        );
+        assert_eq!(
+            GraphError::RouteHalted,
+            g.get_top_result(&rate_req).unwrap_err()
+        );
+
+        g.set_halted(&bittrex, false).unwrap();
+        let result = g.get_top_result(&rate_req).unwrap();
+        assert_eq!(1002.0, result.rate);
+        assert_eq!(None, result.halted_rate);
+        assert_eq!(
+            GraphError::UnknownPair,
+            g.set_halted(
+                &PairRequest::new("BITTREX".to_owned(), "BTC".to_owned(), "EUR".to_owned()),
+                true
+            )
+            .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_rate_every_node_reachable_from_a_source() {
+        const KRAKEN_EUR_PRICE: &[&str] = &[
+            "2017-11-01T09:42:23+00:00",
+            "KRAKEN",
+            "EUR",
+            "JPY",
+            "130.0",
+            "0.0075",
+        ];
+        let mut g = Graph::new();
+        for price in [GDAX_PRICE, KRAKEN_PRICE, KRAKEN_EUR_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let source = ExchangeCurrencyPair::new("KRAKEN".to_owned(), "BTC".to_owned());
+        let results = g.get_rates_from(&source).unwrap();
+        let destinations: Vec<(&str, &str, f64)> = results
+            .iter()
+            .map(|r| {
+                let last = r.paths.back().unwrap();
+                (last.exchange.as_str(), last.currency.as_str(), r.rate)
+            })
+            .collect();
+        // KRAKEN EUR and JPY aren't reachable from BTC
+        assert_eq!(
+            vec![
+                ("GDAX", "BTC", 1.0),
+                ("GDAX", "USD", 1001.0),
+                ("KRAKEN", "USD", 1001.0),
+            ],
+            destinations
+        );
+        assert_eq!(
+            GraphError::UnknownNode,
+            g.get_rates_from(&ExchangeCurrencyPair::new(
+                "KRAKEN".to_owned(),
+                "ETH".to_owned()
+            ))
+            .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_rate_from_a_source_as_one_request_per_destination_would() {
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE, BITTREX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let source = ExchangeCurrencyPair::new("KRAKEN".to_owned(), "BTC".to_owned());
+        let assert_same_as_requests = |g: &Graph| {
+            let from = g.find_node_with("KRAKEN", "BTC").unwrap();
+            let expected: Vec<OptimalRateWithPath> = g
+                .sorted_nodes()
+                .iter()
+                .filter(|n| n.index != from.index)
+                .filter_map(|n| g.best_rate_between(&from, n).unwrap())
+                .collect();
+            assert_eq!(expected, g.get_rates_from(&source).unwrap());
+        };
+
+        // from the cached best rates
+        assert_same_as_requests(&g);
+
+        // in one pass around a halted pair
+        g.set_halted(
+            &PairRequest::new("BITTREX".to_owned(), "BTC".to_owned(), "USD".to_owned()),
+            true,
+        )
+        .unwrap();
+        assert_same_as_requests(&g);
+
+        // and through a profitable cycle, whose targets are unbounded
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 43, 0);
+        for &(source, destination, forward, backward) in
+            [("ETH", "USD", 99.0, 0.01), ("ETH", "BTC", 0.11, 9.0)].iter()
+        {
+            g.update(&PriceUpdateRequest::new(
+                ts,
+                "KRAKEN".to_owned(),
+                source.to_owned(),
+                destination.to_owned(),
+                forward,
+                backward,
+            ));
+        }
+        assert_same_as_requests(&g);
+        assert!(g
+            .get_rates_from(&source)
+            .unwrap()
+            .iter()
+            .all(|r| r.arbitrage_cycle.is_some()));
+    }
+
+    #[test]
+    fn should_build_the_rate_matrix_of_selected_currencies() {
+        let mut g = Graph::new();
+        for price in [KRAKEN_PRICE, GDAX_PRICE].iter() {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        let matrix = g.get_rate_matrix(&["USD".to_owned()]).unwrap();
+        assert_eq!(
+            vec![
+                ExchangeCurrencyPair::new("GDAX".to_owned(), "USD".to_owned()),
+                ExchangeCurrencyPair::new("KRAKEN".to_owned(), "USD".to_owned()),
+            ],
+            matrix.nodes
+        );
+        let rates: Vec<Vec<Option<f64>>> = matrix
+            .rates
+            .iter()
+            .map(|row| row.iter().map(|r| r.as_ref().map(|r| r.rate)).collect())
+            .collect();
+        assert_eq!(vec![vec![None, Some(1.0)], vec![Some(1.0), None]], rates);
+        assert_eq!(
+            ",GDAX USD,KRAKEN USD\nGDAX USD,,1\nKRAKEN USD,1,\n",
+            matrix.to_csv()
+        );
+
+        let matrix = g.get_rate_matrix(&[]).unwrap();
+        assert_eq!(4, matrix.nodes.len());
+        // from GDAX BTC to GDAX USD
+        assert_eq!(Some(1001.0), matrix.rates[0][1].as_ref().map(|r| r.rate));
+
+        // a profitable cycle on KRAKEN makes every linked rate unbounded, but
+        // not BITTREX's own pair
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 43, 0);
+        for &(exchange, source, destination, forward, backward) in [
+            ("KRAKEN", "ETH", "USD", 99.0, 0.01),
+            ("KRAKEN", "ETH", "BTC", 0.11, 9.0),
+            ("BITTREX", "LTC", "EUR", 48.0, 0.02),
+        ]
+        .iter()
+        {
+            g.update(&PriceUpdateRequest::new(
+                ts,
+                exchange.to_owned(),
+                source.to_owned(),
+                destination.to_owned(),
+                forward,
+                backward,
+            ));
+        }
+        let matrix = g.get_rate_matrix(&[]).unwrap();
+        let nodes = g.sorted_nodes();
+        for (i, from) in nodes.iter().enumerate() {
+            for (j, to) in nodes.iter().enumerate() {
+                let expected = if i == j {
+                    None
+                } else {
+                    g.best_rate_between(from, to).unwrap()
+                };
+                assert_eq!(expected, matrix.rates[i][j]);
+            }
+        }
+        assert!(matrix.rates[0][1]
+            .as_ref()
+            .unwrap()
+            .arbitrage_cycle
+            .is_none());
+        assert!(matrix.rates[2][3]
+            .as_ref()
+            .unwrap()
+            .arbitrage_cycle
+            .is_some());
+    }
+
+    #[test]
+    fn should_route_the_same_after_a_snapshot_round_trip() {
+        let mut g = Graph::new();
+        g.set_decimal_mode(Some(DecimalMode::new(6, Rounding::HalfUp)));
+        g.set_max_age(Some(Duration::seconds(3600)));
+        g.set_trading_fee(&validate_trading_fee_input(&["KRAKEN", "0.0026"]).unwrap());
+        g.set_trading_fee(&validate_trading_fee_input(&["GDAX", "BTC", "USD", "0.001"]).unwrap());
+        g.set_transfer_cost(
+            &validate_transfer_cost_input(&["BTC", "KRAKEN", "GDAX", "0.001", "0.0005"]).unwrap(),
+        );
+        g.set_link(&validate_link_input(&["GDAX", "BITTREX", "USD"], false).unwrap());
+        for price in [
+            KRAKEN_PRICE,
+            GDAX_PRICE,
+            BITTREX_PRICE,
+            KRAKEN_PRICE_WITH_LATEST_DATE,
+        ]
+        .iter()
+        {
+            g.update(&validate_price_update_input(price).unwrap());
+        }
+        g.update(&validate_price_update_input(KRAKEN_PRICE).unwrap());
+        g.set_halted(
+            &PairRequest::new("BITTREX".to_owned(), "BTC".to_owned(), "USD".to_owned()),
+            true,
+        )
+        .unwrap();
+        g.remove_pair(&PairRequest::new(
+            "GDAX".to_owned(),
+            "BTC".to_owned(),
+            "USD".to_owned(),
+        ))
+        .unwrap();
+        g.update(&validate_price_update_input(GDAX_PRICE).unwrap());
+
+        let text = g.to_snapshot().to_text();
+        let loaded = Graph::from_snapshot(Snapshot::from_text(&text).unwrap());
+        assert_eq!(text, loaded.to_snapshot().to_text());
+        assert_eq!(
+            format!("{:?}", g.get_nodes()),
+            format!("{:?}", loaded.get_nodes())
+        );
+        assert_eq!(
+            format!("{:?}", g.get_paths()),
+            format!("{:?}", loaded.get_paths())
+        );
+
+        for (from, to) in [
+            ("KRAKEN", "GDAX"),
+            ("GDAX", "KRAKEN"),
+            ("BITTREX", "KRAKEN"),
+        ]
+        .iter()
+        {
+            for (src, dst) in [("BTC", "USD"), ("USD", "BTC")].iter() {
+                let rate_req = || {
+                    ExchangeRateRequest::new(
+                        from.to_string(),
+                        src.to_string(),
+                        to.to_string(),
+                        dst.to_string(),
+                    )
+                    .with_route_count(3)
+                };
+                assert_eq!(
+                    g.get_top_results(&rate_req()),
+                    loaded.get_top_results(&rate_req())
+                );
+                let at = Some(Utc.ymd(2017, 11, 1).and_hms(10, 0, 0));
+                assert_eq!(
+                    g.get_top_results(&rate_req().with_at(at)),
+                    loaded.get_top_results(&rate_req().with_at(at))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_index_thousands_of_nodes() {
+        let exchanges: Vec<String> = (0..30).map(|i| format!("EX{}", i)).collect();
+        let currencies: Vec<String> = (0..60).map(|i| format!("C{}", i)).collect();
+        let ts = Utc.ymd(2017, 11, 1).and_hms(9, 42, 23);
+        let mut g = Graph::new();
+        for exchange in exchanges.iter() {
+            for pair in currencies.windows(2) {
+                g.update(&PriceUpdateRequest::new(
+                    ts,
+                    exchange.clone(),
+                    pair[0].clone(),
+                    pair[1].clone(),
+                    0.5,
+                    1.5,
+                ));
+            }
+        }
+
+        assert_eq!(exchanges.len() * currencies.len(), g.nodes.len());
+        for (i, node) in g.nodes.iter().enumerate() {
+            let found = g.find_node_with(&node.exchange, &node.currency).unwrap();
+            assert_eq!(i, found.index);
+        }
+
+        // two quotes per pair, plus a transfer each way between every two exchanges
+        let exchange_count = exchanges.len();
+        let expected_paths = exchange_count * (currencies.len() - 1) * 2
+            + currencies.len() * exchange_count * (exchange_count - 1);
+        assert_eq!(expected_paths, g.paths.len());
+
+        let middle = g.find_node_with("EX7", "C30").unwrap();
+        assert_eq!(2 + exchange_count - 1, g.adjacency[middle.index].len());
+
+        // a newer quote for a known pair updates in place
+        g.update(&PriceUpdateRequest::new(
+            ts + chrono::Duration::seconds(1),
+            "EX7".to_owned(),
+            "C30".to_owned(),
+            "C31".to_owned(),
+            0.6,
+            1.4,
+        ));
+        assert_eq!(expected_paths, g.paths.len());
+        let next = g.find_node_with("EX7", "C31").unwrap();
+        let forward = g.find_path(middle.index, next.index).unwrap();
+        assert_eq!(0.6, g.paths[forward].weight);
+    }
+
     #[test]
     fn should_throw_path_not_found_error() {
         let mut g = Graph::new();
-        let kraken = validate_price_update_input(&KRAKEN_PRICE, &g);
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
         let rate_req = ExchangeRateRequest::new(
             "KRAKEN".to_owned(),
             "BTC".to_owned(),
@@ -462,9 +2670,9 @@ mod test {
     #[test]
     fn should_throw_invalid_path_error() {
         let mut g = Graph::new();
-        let kraken = validate_price_update_input(&KRAKEN_PRICE, &g);
+        let kraken = validate_price_update_input(KRAKEN_PRICE);
         g.update(&kraken.unwrap());
-        let gdax = validate_price_update_input(&GDAX_PRICE, &g);
+        let gdax = validate_price_update_input(GDAX_PRICE);
         g.update(&gdax.unwrap());
         let rate_req = ExchangeRateRequest::new(
             "KRAKEN".to_owned(),
@@ -0,0 +1,480 @@
+use crate::decimal::*;
+use crate::domain::*;
+use crate::error::*;
+use crate::fees::*;
+use crate::links::*;
+use crate::snapshot::factor_name;
+use crate::transfer::*;
+use std::error::Error;
+use std::fmt;
+
+// Just enough JSON for `--format json`: every value below is printed on one
+// line, and numbers that aren't finite are written as null.
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Decimal(Decimal),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => write!(f, "null"),
+            Json::Decimal(d) => write!(f, "{}", d),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_owned())
+}
+
+// The exact value when decimal mode gave one.
+fn number(value: f64, exact: Option<Decimal>) -> Json {
+    exact.map_or(Json::Number(value), Json::Decimal)
+}
+
+fn node(exchange: &str, currency: &str) -> Json {
+    Json::Object(vec![
+        ("exchange", string(exchange)),
+        ("currency", string(currency)),
+    ])
+}
+
+// One route of an answer; `rank` counts from 1. Each hop after the first
+// carries the quote the route took to reach it.
+pub fn rate_to_json(
+    request: &ExchangeRateRequest,
+    result: &OptimalRateWithPath,
+    rank: usize,
+) -> Json {
+    let hops = result
+        .paths
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let quote = i.checked_sub(1).and_then(|i| result.hops.get(i));
+            Json::Object(vec![
+                ("exchange", string(&p.exchange)),
+                ("currency", string(&p.currency)),
+                (
+                    "weight",
+                    quote.map_or(Json::Null, |h| number(h.weight, h.exact_weight)),
+                ),
+                (
+                    "timestamp",
+                    quote.map_or(Json::Null, |h| string(&h.timestamp.to_rfc3339())),
+                ),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("rate")),
+        (
+            "source",
+            node(&request.source_exchange, &request.source_currency),
+        ),
+        (
+            "destination",
+            node(&request.destination_exchange, &request.destination_currency),
+        ),
+        ("rank", Json::Number(rank as f64)),
+        ("rate", number(result.rate, result.exact_rate)),
+        (
+            "gross_rate",
+            number(result.gross_rate, result.exact_gross_rate),
+        ),
+        (
+            "age_seconds",
+            result
+                .oldest_edge_age
+                .map_or(Json::Null, |a| Json::Number(a.num_seconds() as f64)),
+        ),
+        (
+            "halted_rate",
+            result.halted_rate.map_or(Json::Null, Json::Number),
+        ),
+        ("hops", Json::Array(hops)),
+        ("approximate", Json::Bool(result.approximate)),
+        (
+            "arbitrage_cycle",
+            result
+                .arbitrage_cycle
+                .as_ref()
+                .map_or(Json::Null, arbitrage_cycle_to_json),
+        ),
+    ])
+}
+
+fn arbitrage_cycle_to_json(cycle: &ArbitrageCycle) -> Json {
+    Json::Object(vec![
+        ("gain", Json::Number(cycle.gain)),
+        (
+            "nodes",
+            Json::Array(
+                cycle
+                    .paths
+                    .iter()
+                    .map(|p| node(&p.exchange, &p.currency))
+                    .collect(),
+            ),
+        ),
+    ])
+}
+
+// A cell is null on the diagonal and where no route exists.
+pub fn rate_matrix_to_json(matrix: &RateMatrix) -> Json {
+    let nodes = matrix
+        .nodes
+        .iter()
+        .map(|n| node(&n.exchange, &n.currency))
+        .collect();
+    let rates = matrix
+        .rates
+        .iter()
+        .map(|row| {
+            Json::Array(
+                row.iter()
+                    .map(|r| match r {
+                        Some(r) => Json::Object(vec![
+                            ("rate", number(r.rate, r.exact_rate)),
+                            ("unbounded", Json::Bool(r.arbitrage_cycle.is_some())),
+                            ("approximate", Json::Bool(r.approximate)),
+                        ]),
+                        None => Json::Null,
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("matrix")),
+        ("nodes", Json::Array(nodes)),
+        ("rates", Json::Array(rates)),
+    ])
+}
+
+pub fn arbitrage_cycles_to_json(cycles: &[ArbitrageCycle]) -> Json {
+    Json::Object(vec![
+        ("type", string("arbitrage_cycles")),
+        (
+            "cycles",
+            Json::Array(cycles.iter().map(arbitrage_cycle_to_json).collect()),
+        ),
+    ])
+}
+
+// A fee without a pair applies to every pair of its exchange.
+pub fn trading_fees_to_json(fees: &[TradingFeeRequest]) -> Json {
+    let fees = fees
+        .iter()
+        .map(|f| {
+            Json::Object(vec![
+                ("exchange", string(&f.exchange)),
+                (
+                    "pair",
+                    f.pair
+                        .as_ref()
+                        .map_or(Json::Null, |(a, b)| Json::Array(vec![string(a), string(b)])),
+                ),
+                ("fee", Json::Decimal(f.fee)),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("trading_fees")),
+        ("fees", Json::Array(fees)),
+    ])
+}
+
+pub fn links_to_json(auto_link: bool, links: &[LinkRequest]) -> Json {
+    let links = links
+        .iter()
+        .map(|l| {
+            Json::Object(vec![
+                ("currency", string(&l.currency)),
+                (
+                    "exchanges",
+                    Json::Array(vec![string(&l.exchanges.0), string(&l.exchanges.1)]),
+                ),
+                ("linked", Json::Bool(l.linked)),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("links")),
+        ("auto_link", Json::Bool(auto_link)),
+        ("links", Json::Array(links)),
+    ])
+}
+
+pub fn transfer_costs_to_json(costs: &[TransferCostRequest]) -> Json {
+    let costs = costs
+        .iter()
+        .map(|c| {
+            Json::Object(vec![
+                ("currency", string(&c.currency)),
+                ("from", string(&c.from_exchange)),
+                ("to", string(&c.to_exchange)),
+                ("proportional_fee", Json::Decimal(c.cost.proportional_fee)),
+                ("flat_fee", Json::Decimal(c.cost.flat_fee)),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("transfer_costs")),
+        ("costs", Json::Array(costs)),
+    ])
+}
+
+pub fn nodes_to_json(nodes: &[Node]) -> Json {
+    let nodes = nodes
+        .iter()
+        .map(|n| {
+            Json::Object(vec![
+                ("id", Json::Number(n.id as f64)),
+                ("exchange", string(&n.exchange)),
+                ("currency", string(&n.currency)),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("nodes")),
+        ("nodes", Json::Array(nodes)),
+    ])
+}
+
+pub fn paths_to_json(paths: &[Path]) -> Json {
+    let paths = paths
+        .iter()
+        .map(|p| {
+            Json::Object(vec![
+                ("from", node(&p.start_node.exchange, &p.start_node.currency)),
+                ("to", node(&p.end_node.exchange, &p.end_node.currency)),
+                ("factor", string(factor_name(&p.factor_type))),
+                ("weight", number(p.weight, p.exact_weight)),
+                ("gross_weight", number(p.gross_weight, p.exact_gross_weight)),
+                ("timestamp", string(&p.timestamp.to_rfc3339())),
+                ("halted", Json::Bool(p.halted)),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("type", string("paths")),
+        ("paths", Json::Array(paths)),
+    ])
+}
+
+pub fn update_outcome_to_json(outcome: &UpdateOutcome) -> Json {
+    let (name, links) = match outcome {
+        UpdateOutcome::Inserted(links) => ("inserted", Json::Number(*links as f64)),
+        UpdateOutcome::Updated => ("updated", Json::Null),
+        UpdateOutcome::IgnoredStale => ("ignored_stale", Json::Null),
+        UpdateOutcome::IgnoredDuplicate => ("ignored_duplicate", Json::Null),
+    };
+    Json::Object(vec![
+        ("type", string("update")),
+        ("outcome", string(name)),
+        ("links", links),
+    ])
+}
+
+// `kind` is the stable name of the error, e.g. `PathNotFound`; batch runs also
+// give the line of the failing command.
+pub fn error_to_json(e: &(dyn Error + 'static), line_number: Option<usize>) -> Json {
+    Json::Object(vec![
+        ("type", string("error")),
+        ("kind", string(error_kind(e))),
+        ("message", Json::String(e.to_string())),
+        (
+            "line",
+            line_number.map_or(Json::Null, |l| Json::Number(l as f64)),
+        ),
+    ])
+}
+
+// The kind of the first error of ours down the chain of sources.
+fn error_kind(e: &(dyn Error + 'static)) -> &'static str {
+    macro_rules! kind_of {
+        ($($error:ty),*) => {
+            $(
+                if let Some(e) = e.downcast_ref::<$error>() {
+                    return e.kind();
+                }
+            )*
+        };
+    }
+    kind_of!(
+        GraphError,
+        PriceUpdateRequestValidationError,
+        ExchangeRateRequestValidationError,
+        DecimalModeValidationError,
+        DecimalError,
+        TransferCostValidationError,
+        TransferCostFileError,
+        TradingFeeValidationError,
+        LinkValidationError,
+        TtlValidationError,
+        PairValidationError,
+        ExchangeValidationError,
+        NodeValidationError,
+        RateMatrixError,
+        SnapshotError,
+        WalError,
+        CommandLineError,
+        CommandError,
+        ProtocolError
+    );
+    match e.source() {
+        Some(source) => error_kind(source),
+        None if e.is::<std::io::Error>() => "io",
+        None => "other",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::*;
+    use crate::utility::*;
+
+    #[test]
+    fn should_write_rates_with_their_hops() {
+        let mut g = Graph::new();
+        g.update(
+            &validate_price_update_input(&[
+                "2017-11-01T09:42:23+00:00",
+                "KRAKEN",
+                "BTC",
+                "USD",
+                "1000.0",
+                "0.0009",
+            ])
+            .unwrap(),
+        );
+        let request = validate_exchange_rate_input(&["KRAKEN", "BTC", "KRAKEN", "USD"]).unwrap();
+        let result = g.get_top_results(&request).unwrap();
+        assert_eq!(
+            "{\"type\":\"rate\",\
+             \"source\":{\"exchange\":\"KRAKEN\",\"currency\":\"BTC\"},\
+             \"destination\":{\"exchange\":\"KRAKEN\",\"currency\":\"USD\"},\
+             \"rank\":1,\"rate\":1000,\"gross_rate\":1000,\"age_seconds\":0,\
+             \"halted_rate\":null,\
+             \"hops\":[{\"exchange\":\"KRAKEN\",\"currency\":\"BTC\",\"weight\":null,\"timestamp\":null},\
+             {\"exchange\":\"KRAKEN\",\"currency\":\"USD\",\"weight\":1000,\
+             \"timestamp\":\"2017-11-01T09:42:23+00:00\"}],\
+             \"approximate\":false,\"arbitrage_cycle\":null}",
+            rate_to_json(&request, &result[0], 1).to_string()
+        );
+    }
+
+    #[test]
+    fn should_write_listings() {
+        let mut g = Graph::new();
+        g.update(
+            &validate_price_update_input(&[
+                "2017-11-01T09:42:23+00:00",
+                "KRAKEN",
+                "BTC",
+                "USD",
+                "1000.0",
+                "0.0009",
+            ])
+            .unwrap(),
+        );
+        g.set_trading_fee(&validate_trading_fee_input(&["KRAKEN", "BTC", "USD", "0.001"]).unwrap());
+        g.set_link(&validate_link_input(&["KRAKEN", "GDAX", "BTC"], false).unwrap());
+        assert_eq!(
+            "{\"type\":\"trading_fees\",\"fees\":[{\"exchange\":\"KRAKEN\",\
+             \"pair\":[\"BTC\",\"USD\"],\"fee\":0.001}]}",
+            trading_fees_to_json(&g.get_trading_fees()).to_string()
+        );
+        assert_eq!(
+            "{\"type\":\"links\",\"auto_link\":true,\"links\":[{\"currency\":\"BTC\",\
+             \"exchanges\":[\"GDAX\",\"KRAKEN\"],\"linked\":false}]}",
+            links_to_json(g.is_auto_linking(), &g.get_links()).to_string()
+        );
+        assert_eq!(
+            "{\"type\":\"matrix\",\"nodes\":[{\"exchange\":\"KRAKEN\",\"currency\":\"BTC\"},\
+             {\"exchange\":\"KRAKEN\",\"currency\":\"USD\"}],\
+             \"rates\":[[null,{\"rate\":999,\"unbounded\":false,\"approximate\":false}],\
+             [{\"rate\":0.0008991,\"unbounded\":false,\"approximate\":false},null]]}",
+            rate_matrix_to_json(&g.get_rate_matrix(&[]).unwrap()).to_string()
+        );
+    }
+
+    #[test]
+    fn should_write_errors_with_their_kind() {
+        assert_eq!(
+            "{\"type\":\"error\",\"kind\":\"graph.path_not_found\",\
+             \"message\":\"No path found at the moment. System doesn't have enough data \
+             to provide answer to your request.\",\"line\":3}",
+            error_to_json(&GraphError::PathNotFound, Some(3)).to_string()
+        );
+        assert_eq!(
+            "{\"type\":\"error\",\"kind\":\"command.unknown_command\",\
+             \"message\":\"Invalid Command : :x\\\"\\n\",\"line\":null}",
+            error_to_json(&CommandError::UnknownCommand(":x\"\n".to_owned()), None).to_string()
+        );
+        // wrapped errors report the variant they wrap
+        assert!(error_to_json(
+            &ProtocolError::PriceUpdate(PriceUpdateRequestValidationError::InvalidArgumentNumber),
+            None
+        )
+        .to_string()
+        .contains("\"kind\":\"price_update.invalid_argument_number\""));
+        let e: Box<dyn Error> = Box::new(std::io::Error::other("closed"));
+        assert!(error_to_json(e.as_ref(), None)
+            .to_string()
+            .contains("\"kind\":\"io\""));
+        assert_eq!(
+            "[null,1.5]",
+            Json::Array(vec![Json::Number(f64::INFINITY), Json::Number(1.5)]).to_string()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+
+// (currency, exchange, exchange), exchanges in alphabetical order
+type LinkKey = (String, String, String);
+
+// Joins, or separates, the nodes of a currency on two exchanges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkRequest {
+    pub currency: String,
+    pub exchanges: (String, String),
+    pub linked: bool,
+}
+
+impl LinkRequest {
+    pub fn new(currency: String, exchanges: (String, String), linked: bool) -> Self {
+        LinkRequest {
+            currency,
+            exchanges,
+            linked,
+        }
+    }
+}
+
+// Which nodes of the same currency on different exchanges are joined by
+// filled-up paths, i.e. where the currency can actually be moved. With
+// auto-linking every such pair is joined unless it was unlinked by hand;
+// without it only the pairs linked by hand are.
+#[derive(Debug, Clone)]
+pub struct Links {
+    auto_link: bool,
+    linked: HashSet<LinkKey>,
+    unlinked: HashSet<LinkKey>,
+}
+
+impl Links {
+    pub fn new() -> Links {
+        Links {
+            auto_link: true,
+            linked: HashSet::new(),
+            unlinked: HashSet::new(),
+        }
+    }
+
+    pub fn auto_link(&self) -> bool {
+        self.auto_link
+    }
+
+    pub fn set_auto_link(&mut self, auto_link: bool) {
+        self.auto_link = auto_link;
+    }
+
+    pub fn set(&mut self, request: &LinkRequest) {
+        let key = link_key(
+            &request.currency,
+            &request.exchanges.0,
+            &request.exchanges.1,
+        );
+        if request.linked {
+            self.unlinked.remove(&key);
+            self.linked.insert(key);
+        } else {
+            self.linked.remove(&key);
+            self.unlinked.insert(key);
+        }
+    }
+
+    pub fn allows(&self, currency: &str, a: &str, b: &str) -> bool {
+        let key = link_key(currency, a, b);
+        self.linked.contains(&key) || (self.auto_link && !self.unlinked.contains(&key))
+    }
+
+    // Every pair set by hand, sorted by currency and exchanges.
+    pub fn to_requests(&self) -> Vec<LinkRequest> {
+        let mut requests: Vec<LinkRequest> = self
+            .linked
+            .iter()
+            .map(|k| (k, true))
+            .chain(self.unlinked.iter().map(|k| (k, false)))
+            .map(|((currency, a, b), linked)| {
+                LinkRequest::new(currency.clone(), (a.clone(), b.clone()), linked)
+            })
+            .collect();
+        requests.sort_by(|a, b| (&a.currency, &a.exchanges).cmp(&(&b.currency, &b.exchanges)));
+        requests
+    }
+}
+
+fn link_key(currency: &str, a: &str, b: &str) -> LinkKey {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    (currency.to_owned(), first.to_owned(), second.to_owned())
+}
@@ -1,7 +1,18 @@
+mod all_pairs;
+mod arbitrage;
+mod decimal;
 mod domain;
 mod error;
+mod fees;
 mod graph;
+mod json;
+mod links;
+mod protocol;
+mod routes;
+mod snapshot;
+mod transfer;
 mod utility;
+mod wal;
 
 #[macro_use]
 extern crate quick_error;
@@ -10,153 +21,1010 @@ extern crate chrono;
 extern crate colored;
 
 use crate::domain::*;
+use crate::error::{CommandError, GraphError, WalError};
 use crate::graph::*;
 use colored::*;
+use std::env;
+use std::error::Error;
 use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::process;
 
 use std::io;
 
 fn main() {
-    print_help();
-    let mut g = Graph::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let options = utility::validate_command_line(&args).unwrap_or_else(|e| exit_with(&e));
+
+    // scripts and pipes get neither banner, prompt nor colours, not even on
+    // errors loading the snapshot or the log
+    let batch = options.batch || options.protocol || !io::stdin().is_terminal();
+    let plain = batch || options.format == OutputFormat::Json;
+    if plain {
+        colored::control::set_override(false);
+    }
+    let mut session = open_session(&options).unwrap_or_else(|e| exit_with(e.as_ref()));
+    if !plain {
+        print_help(&mut io::stdout()).unwrap();
+    }
+
+    let stdin = io::stdin();
+    let mut handle = stdin.lock();
+    let mut line_number = 0;
+    let mut failed = false;
+    // in JSON mode stdout carries nothing but JSON
+    let prompt = !batch && options.format == OutputFormat::Text;
     loop {
-        print_prompt();
+        if prompt {
+            print_prompt();
+        }
         let mut buffer = String::new();
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
-        if handle.read_line(&mut buffer).is_ok() {
-            let trimmed_buffer = buffer.trim();
-            if !trimmed_buffer.is_empty() {
-                let args: Vec<&str> = trimmed_buffer.split_whitespace().collect();
-                match args[0] {
-                    PRICE_UPDATE_CMD => {
-                        let ret = utility::validate_price_update_input(&args[1..], &g);
-                        match ret {
-                            Ok(v) => g.update(&v),
-                            Err(e) => println!("{}", e.to_string().red()),
-                        }
-                    }
-                    RATE_REQUEST_CMD => {
-                        let ret = utility::validate_exchange_rate_input(&args[1..]);
-                        match ret {
-                            Ok(v) => display_top_rate_with_paths(&g, &v),
-                            Err(e) => println!("{}", e.to_string().red()),
+        match handle.read_line(&mut buffer) {
+            Ok(0) => break,
+            Ok(_) => line_number += 1,
+            Err(e) => {
+                report_error(options.format, batch, line_number + 1, &e);
+                failed = true;
+                break;
+            }
+        }
+        let trimmed_buffer = buffer.trim();
+        if trimmed_buffer.is_empty() || trimmed_buffer.starts_with(COMMENT_PREFIX) {
+            continue;
+        }
+        let args: Vec<&str> = trimmed_buffer.split_whitespace().collect();
+        let ret = if options.protocol {
+            run_protocol_line(&mut session, &args)
+        } else {
+            run_command(&mut session, &args)
+        };
+        match ret {
+            Ok(Flow::Continue) => (),
+            Ok(Flow::Quit) => break,
+            Err(e) => {
+                report_error(options.format, batch, line_number, e.as_ref());
+                failed = true;
+            }
+        }
+    }
+    if batch && failed {
+        process::exit(1);
+    }
+}
+
+// What the REPL works on between commands.
+struct Session {
+    g: Graph,
+    wal: Option<wal::WriteAheadLog>,
+    // written by compaction
+    snapshot_file: Option<String>,
+    format: OutputFormat,
+}
+
+// Loads the snapshot, if any, and replays the write-ahead log over it.
+fn open_session(options: &CommandLineOptions) -> Result<Session, Box<dyn Error>> {
+    let g = match &options.snapshot_file {
+        Some(file) if std::path::Path::new(file).exists() => {
+            Graph::from_snapshot(snapshot::read_snapshot_file(&[file])?)
+        }
+        _ => Graph::new(),
+    };
+    let mut session = Session {
+        g,
+        wal: None,
+        snapshot_file: options.snapshot_file.clone(),
+        format: options.format,
+    };
+    if let Some(file) = &options.wal_file {
+        let (wal, records) = wal::WriteAheadLog::open(file)?;
+        for r in records.iter() {
+            match r {
+                wal::Record::PriceUpdate(v) => {
+                    session.g.update(v);
+                }
+                wal::Record::Command(command) => {
+                    let args: Vec<&str> = command.split_whitespace().collect();
+                    match change_graph(&mut session.g, &args) {
+                        Err(e) if already_applied(&args, e.as_ref()) => (),
+                        Err(e) => {
+                            return Err(Box::new(WalError::UnreplayableCommand(
+                                command.clone(),
+                                e.to_string(),
+                            )))
                         }
+                        Ok(()) => (),
                     }
-                    DISPLAY_NODE_CMD => println!("{:?}", g.get_nodes()),
-                    DISPLAY_PATH_CMD => println!("{:?}", g.get_paths()),
-                    CLEAR_DATA_CMD => g.clear(),
-                    HELP_CMD => print_help(),
-                    QUIT_CMD => break,
-                    _ => println!("Invalid Command"),
                 }
             }
         }
+        session.wal = Some(wal);
+    }
+    Ok(session)
+}
+
+// A removal or halt of something the snapshot no longer holds: the snapshot
+// was written after the command, so there is nothing left to redo.
+fn already_applied(args: &[&str], e: &(dyn Error + 'static)) -> bool {
+    let missing = matches!(
+        e.downcast_ref::<GraphError>(),
+        Some(GraphError::UnknownPair) | Some(GraphError::UnknownExchange)
+    );
+    missing
+        && matches!(
+            args[0],
+            REMOVE_PAIR_CMD | REMOVE_EXCHANGE_CMD | HALT_CMD | RESUME_CMD
+        )
+}
+
+// Whether two names lead to the same file, existing or not.
+fn same_file(a: &str, b: &str) -> bool {
+    let canonical = |name: &str| {
+        let path = std::path::Path::new(name);
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+        Some(
+            std::fs::canonicalize(directory)
+                .ok()?
+                .join(path.file_name()?),
+        )
+    };
+    a == b || canonical(a).is_some_and(|a| canonical(b) == Some(a))
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+fn run_command(session: &mut Session, args: &[&str]) -> Result<Flow, Box<dyn Error>> {
+    let g = &mut session.g;
+    let json = session.format == OutputFormat::Json;
+    match args[0] {
+        PRICE_UPDATE_CMD => {
+            let v = utility::validate_price_update_input(&args[1..])?;
+            if let Some(wal) = session.wal.as_mut().filter(|_| g.would_accept(&v)) {
+                wal.append(&v)?;
+            }
+            let outcome = g.update(&v);
+            if json {
+                println!("{}", json::update_outcome_to_json(&outcome));
+            } else {
+                display_update_outcome(&outcome);
+            }
+        }
+        _ if changes_graph(args) => {
+            change_graph(g, args)?;
+            if let Some(wal) = session.wal.as_mut() {
+                wal.append_command(&args.join(" "))?;
+            }
+        }
+        RATE_REQUEST_CMD => {
+            let v = utility::validate_exchange_rate_input(&args[1..])?;
+            display_top_rate_with_paths(g, &v, session.format)?;
+        }
+        cmd if cmd.starts_with(RATE_REQUEST_AT_CMD) => {
+            let v = utility::validate_point_in_time_rate_input(
+                &cmd[RATE_REQUEST_AT_CMD.len()..],
+                &args[1..],
+            )?;
+            display_top_rate_with_paths(g, &v, session.format)?;
+        }
+        RATES_FROM_CMD => {
+            let v = utility::validate_node_input(&args[1..])?;
+            display_rates_from(g, &v, session.format)?;
+        }
+        RATE_MATRIX_CMD => {
+            let (currencies, csv_file) = utility::validate_rate_matrix_input(&args[1..])?;
+            display_rate_matrix(g, &currencies, csv_file.as_deref(), session.format)?;
+        }
+        ARBITRAGE_CMD if json => println!(
+            "{}",
+            json::arbitrage_cycles_to_json(&g.get_arbitrage_cycles())
+        ),
+        ARBITRAGE_CMD => display_arbitrage_cycles(g),
+        TRADING_FEE_CMD if args.len() == 1 && json => {
+            println!("{}", json::trading_fees_to_json(&g.get_trading_fees()))
+        }
+        TRADING_FEE_CMD if args.len() == 1 => display_trading_fees(g),
+        LINK_CMD if args.len() == 1 && json => println!(
+            "{}",
+            json::links_to_json(g.is_auto_linking(), &g.get_links())
+        ),
+        LINK_CMD if args.len() == 1 => display_links(g),
+        TRANSFER_COST_CMD if args.len() == 1 && json => {
+            println!("{}", json::transfer_costs_to_json(&g.get_transfer_costs()))
+        }
+        TRANSFER_COST_CMD if args.len() == 1 => display_transfer_costs(g),
+        LOAD_TRANSFER_COSTS_CMD => {
+            for r in utility::read_transfer_cost_file(&args[1..])? {
+                g.set_transfer_cost(&r);
+                // logged entry by entry, as the file may have changed by the replay
+                if let Some(wal) = session.wal.as_mut() {
+                    wal.append_command(&format!(
+                        "{} {} {} {} {} {}",
+                        TRANSFER_COST_CMD,
+                        r.currency,
+                        r.from_exchange,
+                        r.to_exchange,
+                        r.cost.proportional_fee,
+                        r.cost.flat_fee
+                    ))?;
+                }
+            }
+        }
+        // saving over the snapshot the log replays onto must empty the log too
+        SAVE_CMD => match (session.wal.as_mut(), &session.snapshot_file) {
+            (Some(wal), Some(file)) if matches!(args[1..], [target] if same_file(target, file)) => {
+                wal.compact_into(file, &g.to_snapshot())?
+            }
+            _ => snapshot::write_snapshot_file(&args[1..], &g.to_snapshot())?,
+        },
+        LOAD_CMD => {
+            let loaded = Graph::from_snapshot(snapshot::read_snapshot_file(&args[1..])?);
+            // the log can't undo a load, so it starts over from the loaded graph
+            match (session.wal.as_mut(), &session.snapshot_file) {
+                (Some(wal), Some(file)) => wal.compact_into(file, &loaded.to_snapshot())?,
+                (Some(_), None) => return Err(Box::new(WalError::LoadUnavailable)),
+                (None, _) => (),
+            }
+            *g = loaded;
+        }
+        COMPACT_CMD => match (session.wal.as_mut(), &session.snapshot_file) {
+            (Some(wal), Some(file)) => wal.compact_into(file, &g.to_snapshot())?,
+            _ => return Err(Box::new(WalError::CompactionUnavailable)),
+        },
+        DISPLAY_NODE_CMD if json => println!("{}", json::nodes_to_json(&g.get_nodes())),
+        DISPLAY_NODE_CMD => println!("{:?}", g.get_nodes()),
+        DISPLAY_PATH_CMD if json => println!("{}", json::paths_to_json(&g.get_paths())),
+        DISPLAY_PATH_CMD => println!("{:?}", g.get_paths()),
+        HELP_CMD if json => print_help(&mut io::stderr())?,
+        HELP_CMD => print_help(&mut io::stdout())?,
+        QUIT_CMD => return Ok(Flow::Quit),
+        cmd => return Err(Box::new(CommandError::UnknownCommand(cmd.to_owned()))),
+    }
+    Ok(Flow::Continue)
+}
+
+// Commands other than `:u`, `:tload` and `:load` that change the graph; they
+// are logged as typed under `--wal`.
+fn changes_graph(args: &[&str]) -> bool {
+    match args[0] {
+        TRADING_FEE_CMD | LINK_CMD | TRANSFER_COST_CMD => args.len() > 1,
+        TTL_CMD | DECIMAL_MODE_CMD | UNLINK_CMD | AUTO_LINK_CMD | REMOVE_PAIR_CMD | HALT_CMD
+        | RESUME_CMD | REMOVE_EXCHANGE_CMD | CLEAR_DATA_CMD => true,
+        _ => false,
+    }
+}
+
+fn change_graph(g: &mut Graph, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    match args[0] {
+        TTL_CMD => g.set_max_age(utility::validate_ttl_input(&args[1..])?),
+        DECIMAL_MODE_CMD => g.set_decimal_mode(utility::validate_decimal_mode_input(&args[1..])?),
+        TRADING_FEE_CMD => g.set_trading_fee(&utility::validate_trading_fee_input(&args[1..])?),
+        LINK_CMD | UNLINK_CMD => g.set_link(&utility::validate_link_input(
+            &args[1..],
+            args[0] == LINK_CMD,
+        )?),
+        AUTO_LINK_CMD => g.set_auto_link(utility::validate_auto_link_input(&args[1..])?),
+        TRANSFER_COST_CMD => {
+            g.set_transfer_cost(&utility::validate_transfer_cost_input(&args[1..])?)
+        }
+        REMOVE_PAIR_CMD => g.remove_pair(&utility::validate_pair_input(&args[1..])?)?,
+        HALT_CMD | RESUME_CMD => g.set_halted(
+            &utility::validate_pair_input(&args[1..])?,
+            args[0] == HALT_CMD,
+        )?,
+        REMOVE_EXCHANGE_CMD => g.remove_exchange(&utility::validate_exchange_input(&args[1..])?)?,
+        CLEAR_DATA_CMD => g.clear(),
+        cmd => return Err(Box::new(CommandError::UnknownCommand(cmd.to_owned()))),
+    }
+    Ok(())
+}
+
+// Price updates are applied silently; rate requests get their BEST_RATES
+// blocks, one per route.
+fn run_protocol_line(session: &mut Session, args: &[&str]) -> Result<Flow, Box<dyn Error>> {
+    match protocol::parse_line(args)? {
+        protocol::ProtocolLine::PriceUpdate(v) => {
+            let g = &mut session.g;
+            if let Some(wal) = session.wal.as_mut().filter(|_| g.would_accept(&v)) {
+                wal.append(&v)?;
+            }
+            g.update(&v);
+        }
+        protocol::ProtocolLine::ExchangeRate(v) => {
+            for result in session.g.get_top_results(&v)? {
+                print!("{}", protocol::format_best_rates(&v, &result));
+            }
+            io::stdout().flush()?;
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+// Batch runs say where the failing command is, on stderr.
+fn report_error(format: OutputFormat, batch: bool, line_number: usize, e: &(dyn Error + 'static)) {
+    if format == OutputFormat::Json {
+        let json = json::error_to_json(e, Some(line_number).filter(|_| batch));
+        if batch {
+            eprintln!("{}", json);
+        } else {
+            println!("{}", json);
+        }
+    } else if batch {
+        eprintln!("line {}: {}", line_number, e);
+    } else {
+        println!("{}", e.to_string().red());
     }
 }
 
 const PRICE_UPDATE_CMD: &str = ":u";
 const RATE_REQUEST_CMD: &str = ":r";
+const RATE_REQUEST_AT_CMD: &str = ":r@";
+const RATES_FROM_CMD: &str = ":from";
+const RATE_MATRIX_CMD: &str = ":matrix";
+const ARBITRAGE_CMD: &str = ":a";
+const DECIMAL_MODE_CMD: &str = ":decimal";
+const TTL_CMD: &str = ":ttl";
+const TRADING_FEE_CMD: &str = ":fee";
+const LINK_CMD: &str = ":link";
+const UNLINK_CMD: &str = ":unlink";
+const AUTO_LINK_CMD: &str = ":autolink";
+const TRANSFER_COST_CMD: &str = ":t";
+const LOAD_TRANSFER_COSTS_CMD: &str = ":tload";
+const SAVE_CMD: &str = ":save";
+const LOAD_CMD: &str = ":load";
+const COMPACT_CMD: &str = ":compact";
 const DISPLAY_NODE_CMD: &str = ":n";
 const DISPLAY_PATH_CMD: &str = ":p";
+const REMOVE_PAIR_CMD: &str = ":d";
+const REMOVE_EXCHANGE_CMD: &str = ":dx";
+const HALT_CMD: &str = ":halt";
+const RESUME_CMD: &str = ":resume";
 const CLEAR_DATA_CMD: &str = ":c";
 const HELP_CMD: &str = ":h";
 const QUIT_CMD: &str = ":q";
+const COMMENT_PREFIX: &str = "#";
 
-fn print_help() {
+// The help is text only : in JSON mode it goes to stderr.
+fn print_help(out: &mut dyn Write) -> io::Result<()> {
     let opening = "\n\r\n\r◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇ ◇◇";
-    println!("{}", opening.green());
-    println!("{}", "Please use the following commands to interact with the program.\n\rPlease note that commands are case-sensitive.\n\r".green());
-    println!(
+    writeln!(out, "{}", opening.green())?;
+    writeln!(out, "{}", "Please use the following commands to interact with the program.\n\rPlease note that commands are case-sensitive.\n\r".green())?;
+    writeln!(
+        out,
         "{:<25}{}",
         "Commands".green().to_string(),
-        "Description".green().to_string()
-    );
-    println!(
+        "Description".green()
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
         PRICE_UPDATE_CMD,
         &format!(
             "Update price, usage: {} {}",
-            PRICE_UPDATE_CMD.yellow().to_string(),
-            "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0009"
-                .yellow()
-                .to_string()
+            PRICE_UPDATE_CMD.yellow(),
+            "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0009".yellow()
         )
-    );
-    println!(
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
         RATE_REQUEST_CMD,
         &format!(
             "Calculate optimal exchange rate, usage: {} {}",
-            RATE_REQUEST_CMD.yellow().to_string(),
-            "KRAKEN BTC GDAX USD".yellow().to_string()
+            RATE_REQUEST_CMD.yellow(),
+            "KRAKEN BTC GDAX USD [ROUTES] [--max-hops N] [--exclude EX,..] [--only EX,..]".yellow()
         )
-    );
-    println!(
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
-        DISPLAY_NODE_CMD,
+        format!("{}TIME", RATE_REQUEST_AT_CMD),
         &format!(
-            "Display all nodes, usage: {}",
-            DISPLAY_NODE_CMD.yellow().to_string()
+            "Calculate the rate as it was at a time, usage: {}{}",
+            RATE_REQUEST_AT_CMD.yellow(),
+            "2017-11-01T10:00:00Z KRAKEN BTC GDAX USD [ROUTES] [OPTIONS]".yellow()
         )
-    );
-    println!(
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        RATES_FROM_CMD,
+        &format!(
+            "Calculate the best rate to every reachable node, usage: {} {}",
+            RATES_FROM_CMD.yellow(),
+            "KRAKEN BTC".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        RATE_MATRIX_CMD,
+        &format!(
+            "Show or export the best rates between nodes, usage: {} {}",
+            RATE_MATRIX_CMD.yellow(),
+            "[CURRENCIES..] [--csv FILE]".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        ARBITRAGE_CMD,
+        &format!("Find profitable cycles, usage: {}", ARBITRAGE_CMD.yellow())
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        DECIMAL_MODE_CMD,
+        &format!(
+            "Report exact decimal rates rounded to SCALE digits (half-up, half-even, down, up), usage: {} {}",
+            DECIMAL_MODE_CMD.yellow(),
+            "8 half-even | off".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        TTL_CMD,
+        &format!(
+            "Ignore quotes older than SECONDS before the newest one, usage: {} {}",
+            TTL_CMD.yellow(),
+            "300 | off".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        TRADING_FEE_CMD,
+        &format!(
+            "Set the trading fee of an exchange or of one of its pairs, or list them, usage: {} {}",
+            TRADING_FEE_CMD.yellow(),
+            "KRAKEN [BTC USD] 0.0026".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        LINK_CMD,
+        &format!(
+            "Allow moving a currency between two exchanges, or list the links, usage: {} {}",
+            LINK_CMD.yellow(),
+            "KRAKEN GDAX BTC".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        UNLINK_CMD,
+        &format!(
+            "Forbid moving a currency between two exchanges, usage: {} {}",
+            UNLINK_CMD.yellow(),
+            "KRAKEN GDAX BTC".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        AUTO_LINK_CMD,
+        &format!(
+            "Link every currency across exchanges unless unlinked, or only where linked, usage: {} {}",
+            AUTO_LINK_CMD.yellow(),
+            "on | off".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        TRANSFER_COST_CMD,
+        &format!(
+            "Set the fees for moving a currency between exchanges, or list them, usage: {} {}",
+            TRANSFER_COST_CMD.yellow(),
+            "BTC KRAKEN GDAX 0.001 0.0005".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        LOAD_TRANSFER_COSTS_CMD,
+        &format!(
+            "Load transfer fees from a file of {} lines, usage: {} {}",
+            TRANSFER_COST_CMD.yellow(),
+            LOAD_TRANSFER_COSTS_CMD.yellow(),
+            "transfer-costs.txt".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        DISPLAY_NODE_CMD,
+        &format!("Display all nodes, usage: {}", DISPLAY_NODE_CMD.yellow())
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
         DISPLAY_PATH_CMD,
+        &format!("Display all paths, usage: {}", DISPLAY_PATH_CMD.yellow())
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        REMOVE_PAIR_CMD,
         &format!(
-            "Display all paths, usage: {}",
-            DISPLAY_PATH_CMD.yellow().to_string()
+            "Remove a pair, usage: {} {}",
+            REMOVE_PAIR_CMD.yellow(),
+            "KRAKEN BTC USD".yellow()
         )
-    );
-    println!(
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        REMOVE_EXCHANGE_CMD,
+        &format!(
+            "Remove an exchange and all its pairs, usage: {} {}",
+            REMOVE_EXCHANGE_CMD.yellow(),
+            "KRAKEN".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        HALT_CMD,
+        &format!(
+            "Keep routes off a pair, usage: {} {}",
+            HALT_CMD.yellow(),
+            "KRAKEN BTC USD".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        RESUME_CMD,
+        &format!(
+            "Let routes use a halted pair again, usage: {} {}",
+            RESUME_CMD.yellow(),
+            "KRAKEN BTC USD".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        SAVE_CMD,
+        &format!(
+            "Save prices and settings to a file, usage: {} {}",
+            SAVE_CMD.yellow(),
+            "snapshot.txt".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        LOAD_CMD,
+        &format!(
+            "Replace prices and settings with a saved file, usage: {} {}",
+            LOAD_CMD.yellow(),
+            "snapshot.txt".yellow()
+        )
+    )?;
+    writeln!(
+        out,
+        "{:<16}{}",
+        COMPACT_CMD,
+        &format!(
+            "Fold the write-ahead log into the snapshot, usage: {}",
+            COMPACT_CMD.yellow()
+        )
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
         CLEAR_DATA_CMD,
         &format!(
             "Clear the existing data, usage: {}",
-            CLEAR_DATA_CMD.yellow().to_string()
+            CLEAR_DATA_CMD.yellow()
         )
-    );
-    println!(
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
         HELP_CMD,
-        &format!(
-            "Display this help, usage: {}",
-            HELP_CMD.yellow().to_string()
-        )
-    );
-    println!(
+        &format!("Display this help, usage: {}", HELP_CMD.yellow())
+    )?;
+    writeln!(
+        out,
         "{:<16}{}",
         QUIT_CMD,
-        &format!("Quit, usage: {}", QUIT_CMD.yellow().to_string())
-    );
-    println!();
+        &format!("Quit, usage: {}", QUIT_CMD.yellow())
+    )?;
+    writeln!(out)?;
 
-    io::stdout().flush().unwrap();
+    out.flush()
+}
+
+// Startup failures leave nothing to work with. A bad command line comes
+// before the colour mode is known, so a redirected stderr gets no colour.
+fn exit_with(e: &dyn Error) -> ! {
+    if io::stderr().is_terminal() {
+        eprintln!("{}", e.to_string().red());
+    } else {
+        eprintln!("{}", e);
+    }
+    process::exit(1)
 }
 
 fn print_prompt() {
-    print!("{}", "◇◇〉".green().to_string());
+    print!("{}", "◇◇〉".green());
     io::stdout().flush().unwrap();
 }
 
-fn display_top_rate_with_paths(g: &Graph, req: &ExchangeRateRequest) {
-    let result = g.get_top_result(&req);
-    match result {
-        Ok(v) => {
-            let mut h = format!(
-                "BEST_RATES_BEGIN {} {} {} {} {}",
-                req.source_exchange,
-                req.source_currency,
-                req.destination_exchange,
-                req.destination_currency,
-                v.rate
+fn display_top_rate_with_paths(
+    g: &Graph,
+    req: &ExchangeRateRequest,
+    format: OutputFormat,
+) -> Result<(), GraphError> {
+    let routes = g.get_top_results(req)?;
+    if format == OutputFormat::Json {
+        for (rank, v) in routes.iter().enumerate() {
+            println!("{}", json::rate_to_json(req, v, rank + 1));
+        }
+        return Ok(());
+    }
+    let mut arbitrage_cycle = None;
+    for (rank, v) in routes.into_iter().enumerate() {
+        let rate = match v.exact_rate {
+            Some(exact_rate) => exact_rate.to_string(),
+            None => v.rate.to_string(),
+        };
+        let mut h = format!(
+            "BEST_RATES_BEGIN {} {} {} {} {}",
+            req.source_exchange,
+            req.source_currency,
+            req.destination_exchange,
+            req.destination_currency,
+            rate
+        );
+        if v.gross_rate != v.rate {
+            let gross_rate = match v.exact_gross_rate {
+                Some(exact_rate) => exact_rate.to_string(),
+                None => v.gross_rate.to_string(),
+            };
+            h.push_str(&format!(" GROSS {}", gross_rate));
+        }
+        if let Some(age) = v.oldest_edge_age {
+            h.push_str(&format!(" AGE {}s", age.num_seconds()));
+        }
+        if req.route_count > 1 {
+            h.push_str(&format!(" RANK {}", rank + 1));
+        }
+        for p in v.paths {
+            h.push_str(&format!("\n\r{}, {}", p.exchange, p.currency));
+        }
+        h.push_str("\nBEST_RATES_END");
+        println!("{}", h.blue());
+        if let Some(halted_rate) = v.halted_rate {
+            println!(
+                "{}",
+                format!(
+                    "A better rate of {} exists through pairs that are halted.",
+                    halted_rate
+                )
+                .yellow()
+            );
+        }
+        if v.approximate {
+            println!(
+                "{}",
+                "The search for this route was cut short: a better route may exist.".yellow()
             );
-            for p in v.paths {
-                h.push_str(&format!("\n\r{}, {}", p.exchange, p.currency));
+        }
+        arbitrage_cycle = v.arbitrage_cycle;
+    }
+    if let Some(c) = arbitrage_cycle {
+        println!(
+            "{}",
+            "The best rate is unbounded: the route can loop through this profitable cycle."
+                .yellow()
+        );
+        println!("{}", format_arbitrage_cycle(&c).yellow());
+    }
+    Ok(())
+}
+
+fn display_trading_fees(g: &Graph) {
+    let fees = g.get_trading_fees();
+    if fees.is_empty() {
+        println!("{}", "All trades are free.".blue());
+    }
+    for f in fees {
+        let line = match f.pair {
+            Some((a, b)) => format!("{} {} {} {}", f.exchange, a, b, f.fee),
+            None => format!("{} {}", f.exchange, f.fee),
+        };
+        println!("{}", line.blue());
+    }
+}
+
+fn display_links(g: &Graph) {
+    let mode = if g.is_auto_linking() { "on" } else { "off" };
+    println!("{}", format!("Auto-linking is {}.", mode).blue());
+    for l in g.get_links() {
+        let cmd = if l.linked { LINK_CMD } else { UNLINK_CMD };
+        println!(
+            "{}",
+            format!("{} {} {} {}", cmd, l.exchanges.0, l.exchanges.1, l.currency).blue()
+        );
+    }
+}
+
+fn display_transfer_costs(g: &Graph) {
+    let costs = g.get_transfer_costs();
+    if costs.is_empty() {
+        println!("{}", "All transfers are free.".blue());
+    }
+    for c in costs {
+        println!(
+            "{}",
+            format!(
+                "{} {} {} {} {}",
+                c.currency,
+                c.from_exchange,
+                c.to_exchange,
+                c.cost.proportional_fee,
+                c.cost.flat_fee
+            )
+            .blue()
+        );
+    }
+}
+
+fn display_rates_from(
+    g: &Graph,
+    source: &ExchangeCurrencyPair,
+    format: OutputFormat,
+) -> Result<(), GraphError> {
+    let results = g.get_rates_from(source)?;
+    if results.is_empty() {
+        return Err(GraphError::PathNotFound);
+    }
+    if format == OutputFormat::Json {
+        // one answer per destination, as `:r` would give it
+        for r in results.iter() {
+            let destination = r.paths.back().ok_or(GraphError::Critical)?;
+            let request = ExchangeRateRequest::new(
+                source.exchange.clone(),
+                source.currency.clone(),
+                destination.exchange.clone(),
+                destination.currency.clone(),
+            );
+            println!("{}", json::rate_to_json(&request, r, 1));
+        }
+        return Ok(());
+    }
+    let rows: Vec<(String, String, String)> = results
+        .iter()
+        .map(|r| {
+            let destination = r
+                .paths
+                .back()
+                .map(|p| format!("{} {}", p.exchange, p.currency))
+                .unwrap_or_default();
+            let mut rate = match r.exact_rate {
+                Some(exact_rate) => exact_rate.to_string(),
+                None => r.rate.to_string(),
+            };
+            if r.arbitrage_cycle.is_some() {
+                rate.push_str(" (unbounded)");
+            }
+            if r.approximate {
+                rate.push_str(" (approximate)");
             }
-            h.push_str("\nBEST_RATES_END");
-            println!("{}", h.blue().to_string());
+            let route: Vec<String> = r
+                .paths
+                .iter()
+                .map(|p| format!("{} {}", p.exchange, p.currency))
+                .collect();
+            (destination, rate, route.join(" -> "))
+        })
+        .collect();
+    let header = ("DESTINATION", "RATE", "ROUTE");
+    let destination_width = rows
+        .iter()
+        .map(|r| r.0.len())
+        .chain(Some(header.0.len()))
+        .max()
+        .unwrap_or(0);
+    let rate_width = rows
+        .iter()
+        .map(|r| r.1.len())
+        .chain(Some(header.1.len()))
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{}",
+        format!(
+            "{:<dw$}  {:<rw$}  {}",
+            header.0,
+            header.1,
+            header.2,
+            dw = destination_width,
+            rw = rate_width
+        )
+        .blue()
+    );
+    for (destination, rate, route) in rows {
+        println!(
+            "{}",
+            format!(
+                "{:<dw$}  {:<rw$}  {}",
+                destination,
+                rate,
+                route,
+                dw = destination_width,
+                rw = rate_width
+            )
+            .blue()
+        );
+    }
+    Ok(())
+}
+
+fn display_rate_matrix(
+    g: &Graph,
+    currencies: &[String],
+    csv_file: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let matrix = g.get_rate_matrix(currencies)?;
+    if let Some(file) = csv_file {
+        return Ok(utility::write_rate_matrix_csv(file, &matrix)?);
+    }
+    if matrix.nodes.is_empty() {
+        return Err(Box::new(GraphError::PathNotFound));
+    }
+    if format == OutputFormat::Json {
+        println!("{}", json::rate_matrix_to_json(&matrix));
+        return Ok(());
+    }
+    let labels = matrix.labels();
+    let cells = matrix.cells();
+    let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+    // each column fits its label and its widest rate
+    let widths: Vec<usize> = labels
+        .iter()
+        .enumerate()
+        .map(|(j, l)| {
+            cells
+                .iter()
+                .map(|row| row[j].len())
+                .fold(l.len(), usize::max)
+        })
+        .collect();
+    let mut h = format!("{:<w$}", "", w = label_width);
+    for (l, w) in labels.iter().zip(widths.iter()) {
+        h.push_str(&format!("  {:>w$}", l, w = w));
+    }
+    for (l, row) in labels.iter().zip(cells.iter()) {
+        h.push_str(&format!("\n{:<w$}", l, w = label_width));
+        for (c, w) in row.iter().zip(widths.iter()) {
+            h.push_str(&format!(
+                "  {:>w$}",
+                if c.is_empty() { "-" } else { c },
+                w = w
+            ));
         }
-        Err(e) => println!("{}", e.to_string().red()),
+    }
+    println!("{}", h.blue());
+    Ok(())
+}
+
+fn display_update_outcome(outcome: &UpdateOutcome) {
+    match outcome {
+        UpdateOutcome::Inserted(links) => println!(
+            "{}",
+            format!("New pair inserted with {} transfer link(s).", links).blue()
+        ),
+        UpdateOutcome::Updated => println!("{}", "Pair updated.".blue()),
+        UpdateOutcome::IgnoredStale => println!(
+            "{}",
+            "Ignored as stale : a newer quote is already stored.".yellow()
+        ),
+        UpdateOutcome::IgnoredDuplicate => println!(
+            "{}",
+            "Ignored as duplicate : a quote with this timestamp is already stored.".yellow()
+        ),
+    }
+}
+
+fn display_arbitrage_cycles(g: &Graph) {
+    let cycles = g.get_arbitrage_cycles();
+    if cycles.is_empty() {
+        println!("{}", "No arbitrage cycle found.".blue());
+    }
+    for c in cycles {
+        println!("{}", format_arbitrage_cycle(&c).blue());
+    }
+}
+
+fn format_arbitrage_cycle(c: &ArbitrageCycle) -> String {
+    let mut h = format!("ARBITRAGE_BEGIN {}", c.gain);
+    for p in c.paths.iter() {
+        h.push_str(&format!("\n\r{}, {}", p.exchange, p.currency));
+    }
+    h.push_str("\nARBITRAGE_END");
+    h
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn should_restart_from_a_snapshot_saved_over_the_log() {
+        let directory = std::env::temp_dir();
+        let wal_file = directory.join(format!("main-wal-{}.log", process::id()));
+        let snapshot_file = directory.join(format!("main-snapshot-{}.txt", process::id()));
+        let _ = fs::remove_file(&wal_file);
+        let _ = fs::remove_file(&snapshot_file);
+        let options = CommandLineOptions {
+            batch: true,
+            wal_file: Some(wal_file.to_str().unwrap().to_owned()),
+            snapshot_file: Some(snapshot_file.to_str().unwrap().to_owned()),
+            ..Default::default()
+        };
+        let run = |session: &mut Session, line: &str| {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            assert!(run_command(session, &args).is_ok());
+        };
+
+        let mut session = open_session(&options).unwrap();
+        run(
+            &mut session,
+            ":u 2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000 0.0009",
+        );
+        run(
+            &mut session,
+            ":u 2017-11-01T09:43:23+00:00 GDAX BTC USD 1001 0.0008",
+        );
+        // a repeated tick changes nothing, so it isn't logged
+        run(
+            &mut session,
+            ":u 2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000 0.0009",
+        );
+        assert_eq!(2, fs::read_to_string(&wal_file).unwrap().lines().count());
+        run(&mut session, ":d GDAX BTC USD");
+        run(
+            &mut session,
+            &format!(":save {}", snapshot_file.to_str().unwrap()),
+        );
+        assert_eq!(0, fs::metadata(&wal_file).unwrap().len());
+        run(&mut session, ":halt KRAKEN BTC USD");
+        drop(session);
+
+        // the save emptied the log, so only the halt is replayed
+        let session = open_session(&options).unwrap();
+        let trades: Vec<Path> = session
+            .g
+            .get_paths()
+            .into_iter()
+            .filter(|p| p.is_trade())
+            .collect();
+        assert_eq!(2, trades.len());
+        assert!(trades
+            .iter()
+            .all(|p| p.start_node.exchange == "KRAKEN" && p.halted));
+        drop(session);
+
+        // removals and halts of a pair the snapshot no longer has are no-ops
+        fs::write(&wal_file, ":d GDAX BTC USD\n:resume GDAX BTC USD\n").unwrap();
+        assert!(open_session(&options).is_ok());
+        fs::write(&wal_file, ":fee KRAKEN 2\n").unwrap();
+        assert!(open_session(&options).is_err());
+
+        fs::remove_file(&wal_file).unwrap();
+        fs::remove_file(&snapshot_file).unwrap();
     }
 }
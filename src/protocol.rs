@@ -0,0 +1,97 @@
+use crate::domain::*;
+use crate::error::*;
+use crate::utility::*;
+
+// The plain line protocol spoken by upstream feeders, without the REPL's `:`
+// commands:
+//   2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0009
+//   EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD
+pub const EXCHANGE_RATE_REQUEST: &str = "EXCHANGE_RATE_REQUEST";
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolLine {
+    PriceUpdate(PriceUpdateRequest),
+    ExchangeRate(ExchangeRateRequest),
+}
+
+pub fn parse_line(args: &[&str]) -> Result<ProtocolLine, ProtocolError> {
+    match args.split_first() {
+        Some((&EXCHANGE_RATE_REQUEST, rest)) => Ok(ProtocolLine::ExchangeRate(
+            validate_exchange_rate_input(rest)?,
+        )),
+        _ => Ok(ProtocolLine::PriceUpdate(validate_price_update_input(
+            args,
+        )?)),
+    }
+}
+
+// One BEST_RATES block with `\n` line endings, the last one included.
+pub fn format_best_rates(request: &ExchangeRateRequest, result: &OptimalRateWithPath) -> String {
+    let rate = match result.exact_rate {
+        Some(exact_rate) => exact_rate.to_string(),
+        None => result.rate.to_string(),
+    };
+    let mut h = format!(
+        "BEST_RATES_BEGIN {} {} {} {} {}\n",
+        request.source_exchange,
+        request.source_currency,
+        request.destination_exchange,
+        request.destination_currency,
+        rate
+    );
+    for p in result.paths.iter() {
+        h.push_str(&format!("{}, {}\n", p.exchange, p.currency));
+    }
+    h.push_str("BEST_RATES_END\n");
+    h
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::*;
+
+    #[test]
+    fn should_answer_in_the_exact_wire_format() {
+        let mut g = Graph::new();
+        for line in [
+            "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0009",
+            "2017-11-01T09:43:23+00:00 GDAX BTC USD 1001.0 0.0008",
+        ]
+        .iter()
+        {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match parse_line(&args).unwrap() {
+                ProtocolLine::PriceUpdate(request) => g.update(&request),
+                other => panic!("unexpected {:?}", other),
+            };
+        }
+
+        let args = ["EXCHANGE_RATE_REQUEST", "KRAKEN", "BTC", "GDAX", "USD"];
+        let request = match parse_line(&args).unwrap() {
+            ProtocolLine::ExchangeRate(request) => request,
+            other => panic!("unexpected {:?}", other),
+        };
+        let result = g.get_top_results(&request).unwrap();
+        assert_eq!(
+            "BEST_RATES_BEGIN KRAKEN BTC GDAX USD 1001\n\
+             KRAKEN, BTC\n\
+             GDAX, BTC\n\
+             GDAX, USD\n\
+             BEST_RATES_END\n",
+            format_best_rates(&request, &result[0])
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_lines() {
+        assert_eq!(
+            ProtocolError::ExchangeRate(ExchangeRateRequestValidationError::InvalidArgumentNumber),
+            parse_line(&["EXCHANGE_RATE_REQUEST", "KRAKEN", "BTC"]).unwrap_err()
+        );
+        assert_eq!(
+            ProtocolError::PriceUpdate(PriceUpdateRequestValidationError::InvalidArgumentNumber),
+            parse_line(&["KRAKEN", "BTC", "USD"]).unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use crate::all_pairs::*;
+use crate::arbitrage::{self, Route};
+use crate::domain::*;
+use std::cmp::Ordering;
+
+// Up to `k` best loop-free routes between the ends of `best`, best rate first,
+// with Yen's algorithm: every further route leaves an earlier one at some node
+// (the spur) and takes the best route from there that avoids the edges already
+// taken from that point by routes sharing the same beginning. `best` must be the
+// best route; all routes are restricted to the nodes marked in `usable`, the
+// paths marked in `usable_paths` and to at most `max_hops` edges. A route is
+// approximate when the search that found its last part was cut short.
+pub fn k_best_routes(
+    paths: &[Path],
+    adjacency: &[Vec<usize>],
+    usable: &[bool],
+    usable_paths: &[bool],
+    best: Route,
+    k: usize,
+    max_hops: Option<usize>,
+) -> Vec<Route> {
+    let to = best.nodes[best.nodes.len() - 1];
+    let mut found = vec![best];
+    let mut candidates: Vec<(f64, Route)> = vec![];
+
+    while found.len() < k {
+        let last = found[found.len() - 1].nodes.clone();
+        for i in 0..last.len() - 1 {
+            let root = &last[..=i];
+            let mut spur_paths = usable_paths.to_vec();
+            for route in found
+                .iter()
+                .map(|r| &r.nodes)
+                .filter(|r| r.len() > i + 1 && r[..=i] == *root)
+            {
+                if let Some(p) = path_between(paths, adjacency, route[i], route[i + 1]) {
+                    spur_paths[p] = false;
+                }
+            }
+            let mut spur_usable = usable.to_vec();
+            for &n in root[..i].iter() {
+                spur_usable[n] = false;
+            }
+
+            let spur_hops = max_hops.map(|h| h - i);
+            if let Some(spur) = best_route(
+                paths,
+                adjacency,
+                &spur_usable,
+                &spur_paths,
+                root[i],
+                to,
+                spur_hops,
+            ) {
+                let route = Route {
+                    nodes: root[..i].iter().chain(spur.nodes.iter()).cloned().collect(),
+                    approximate: spur.approximate,
+                };
+                let known = |r: &Route| r.nodes == route.nodes;
+                if !found.iter().any(known) && !candidates.iter().any(|(_, c)| known(c)) {
+                    candidates.push((route_cost(paths, adjacency, &route.nodes), route));
+                }
+            }
+        }
+
+        let next = (0..candidates.len()).min_by(|&a, &b| {
+            candidates[a]
+                .0
+                .partial_cmp(&candidates[b].0)
+                .unwrap_or(Ordering::Equal)
+        });
+        match next {
+            Some(i) => found.push(candidates.remove(i).1),
+            None => break,
+        }
+    }
+    found
+}
+
+// Best route from `from` to `to` over the usable nodes and paths, taking at
+// most `max_hops` paths. Bellman–Ford while no
+// profitable cycle is in reach, since the costs can be negative; otherwise the
+// best route visiting each node once.
+pub fn best_route(
+    paths: &[Path],
+    adjacency: &[Vec<usize>],
+    usable: &[bool],
+    usable_paths: &[bool],
+    from: usize,
+    to: usize,
+    max_hops: Option<usize>,
+) -> Option<Route> {
+    let n = usable.len();
+    let edges: Vec<(usize, usize, f64)> = paths
+        .iter()
+        .enumerate()
+        .filter(|&(i, p)| usable_paths[i] && usable[p.start_node.index] && usable[p.end_node.index])
+        .map(|(_, p)| (p.start_node.index, p.end_node.index, cost_of(p.weight)))
+        .collect();
+
+    let mut cost = vec![f64::INFINITY; n];
+    let mut pred: Vec<Option<usize>> = vec![None; n];
+    cost[from] = 0.0;
+    // a shortest route has fewer than n edges, so a change in the n-th pass
+    // means a profitable cycle
+    let settled = (0..n).any(|_| !relax(&edges, &mut cost, &mut pred));
+    if !settled {
+        return arbitrage::best_simple_path(
+            paths,
+            adjacency,
+            usable,
+            usable_paths,
+            from,
+            to,
+            max_hops,
+        );
+    }
+    if cost[to] == f64::INFINITY {
+        return None;
+    }
+
+    let nodes = match max_hops {
+        Some(h) if h < n => bounded_route(&edges, n, from, to, h),
+        _ => {
+            let mut route = vec![to];
+            let mut current = to;
+            while current != from {
+                if route.len() > n {
+                    return None;
+                }
+                current = pred[current]?;
+                route.push(current);
+            }
+            route.reverse();
+            Some(route)
+        }
+    };
+    nodes.map(Route::exact)
+}
+
+// One Bellman–Ford pass in place. Returns whether any cost went down.
+fn relax(edges: &[(usize, usize, f64)], cost: &mut [f64], pred: &mut [Option<usize>]) -> bool {
+    let mut changed = false;
+    for &(from, to, edge_cost) in edges.iter() {
+        let candidate = cost[from] + edge_cost;
+        if candidate < cost[to] - EPSILON {
+            cost[to] = candidate;
+            pred[to] = Some(from);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Best route of at most `max_hops` edges. Pass k only extends the routes of
+// pass k - 1, so after it `cost` holds the best rates over at most k edges; the
+// predecessors of every pass are kept to walk the route back.
+fn bounded_route(
+    edges: &[(usize, usize, f64)],
+    n: usize,
+    from: usize,
+    to: usize,
+    max_hops: usize,
+) -> Option<Vec<usize>> {
+    let mut cost = vec![f64::INFINITY; n];
+    let mut pred: Vec<Option<usize>> = vec![None; n];
+    cost[from] = 0.0;
+    let mut layers = Vec::with_capacity(max_hops);
+    for _ in 0..max_hops {
+        let previous = cost.clone();
+        for &(u, v, edge_cost) in edges.iter() {
+            let candidate = previous[u] + edge_cost;
+            if candidate < cost[v] - EPSILON {
+                cost[v] = candidate;
+                pred[v] = Some(u);
+            }
+        }
+        layers.push(pred.clone());
+    }
+
+    if cost[to] == f64::INFINITY {
+        return None;
+    }
+    let mut route = vec![to];
+    let mut current = to;
+    for layer in layers.iter().rev() {
+        if current == from {
+            break;
+        }
+        current = layer[current]?;
+        route.push(current);
+    }
+    if current != from {
+        return None;
+    }
+    route.reverse();
+    Some(route)
+}
+
+fn route_cost(paths: &[Path], adjacency: &[Vec<usize>], route: &[usize]) -> f64 {
+    route
+        .windows(2)
+        .map(|w| match path_between(paths, adjacency, w[0], w[1]) {
+            Some(p) => cost_of(paths[p].weight),
+            None => f64::INFINITY,
+        })
+        .sum()
+}
+
+fn path_between(paths: &[Path], adjacency: &[Vec<usize>], from: usize, to: usize) -> Option<usize> {
+    adjacency[from]
+        .iter()
+        .cloned()
+        .find(|&p| paths[p].end_node.index == to)
+}
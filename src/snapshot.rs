@@ -0,0 +1,351 @@
+use crate::decimal::*;
+use crate::domain::*;
+use crate::error::*;
+use crate::fees::*;
+use crate::links::*;
+use crate::transfer::*;
+use crate::utility::*;
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::rc::Rc;
+
+// Bumped whenever the layout below changes; older snapshots are refused.
+pub const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_HEADER: &str = "SNAPSHOT";
+
+// Everything a graph holds. Nodes are listed in index order; paths refer to
+// them by id.
+//
+// Layout, one item per line:
+//   SNAPSHOT 1
+//   DECIMAL 8 half-even | DECIMAL off
+//   TTL 600 | TTL off
+//   AUTOLINK on | AUTOLINK off
+//   FEE KRAKEN [BTC USD] 0.0026
+//   LINK KRAKEN GDAX BTC | UNLINK KRAKEN GDAX BTC
+//   TRANSFER BTC KRAKEN GDAX 0.001 0.0005
+//   NEXT_NODE_ID 4
+//   NODE 0 KRAKEN BTC
+//   PATH 0 1 forward open 2017-11-01T09:42:23+00:00 997.4 997.4 1000 1000
+//   VERSION 2017-11-01T09:42:23+00:00 1000 1000
+// Each PATH is followed by its history, oldest first. Exact weights are `-`
+// when unknown.
+pub struct Snapshot {
+    pub decimal_mode: Option<DecimalMode>,
+    pub max_age: Option<Duration>,
+    pub auto_link: bool,
+    pub trading_fees: Vec<TradingFeeRequest>,
+    pub links: Vec<LinkRequest>,
+    pub transfer_costs: Vec<TransferCostRequest>,
+    pub next_node_id: usize,
+    pub nodes: Vec<Rc<Node>>,
+    pub paths: Vec<Path>,
+}
+
+impl Snapshot {
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![format!("{} {}", SNAPSHOT_HEADER, SNAPSHOT_VERSION)];
+        lines.push(match self.decimal_mode {
+            Some(mode) => format!("DECIMAL {} {}", mode.scale, mode.rounding),
+            None => "DECIMAL off".to_owned(),
+        });
+        lines.push(match self.max_age {
+            Some(max_age) => format!("TTL {}", max_age.num_seconds()),
+            None => "TTL off".to_owned(),
+        });
+        lines.push(format!(
+            "AUTOLINK {}",
+            if self.auto_link { "on" } else { "off" }
+        ));
+        for f in self.trading_fees.iter() {
+            lines.push(match &f.pair {
+                Some((a, b)) => format!("FEE {} {} {} {}", f.exchange, a, b, f.fee),
+                None => format!("FEE {} {}", f.exchange, f.fee),
+            });
+        }
+        for l in self.links.iter() {
+            lines.push(format!(
+                "{} {} {} {}",
+                if l.linked { "LINK" } else { "UNLINK" },
+                l.exchanges.0,
+                l.exchanges.1,
+                l.currency
+            ));
+        }
+        for c in self.transfer_costs.iter() {
+            lines.push(format!(
+                "TRANSFER {} {} {} {} {}",
+                c.currency,
+                c.from_exchange,
+                c.to_exchange,
+                c.cost.proportional_fee,
+                c.cost.flat_fee
+            ));
+        }
+        lines.push(format!("NEXT_NODE_ID {}", self.next_node_id));
+        for n in self.nodes.iter() {
+            lines.push(format!("NODE {} {} {}", n.id, n.exchange, n.currency));
+        }
+        for p in self.paths.iter() {
+            lines.push(format!(
+                "PATH {} {} {} {} {} {} {} {} {}",
+                p.start_node.id,
+                p.end_node.id,
+                factor_name(&p.factor_type),
+                if p.halted { "halted" } else { "open" },
+                p.timestamp.to_rfc3339(),
+                p.weight,
+                or_dash(p.exact_weight),
+                p.gross_weight,
+                or_dash(p.exact_gross_weight)
+            ));
+            for v in p.history.iter() {
+                lines.push(format!(
+                    "VERSION {} {} {}",
+                    v.timestamp.to_rfc3339(),
+                    v.gross_weight,
+                    or_dash(v.exact_gross_weight)
+                ));
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    pub fn from_text(text: &str) -> Result<Snapshot, SnapshotError> {
+        let mut lines = text.lines().enumerate();
+        match lines
+            .next()
+            .map(|(_, l)| l.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(ref header) if header.len() == 2 && header[0] == SNAPSHOT_HEADER => {
+                if header[1] != SNAPSHOT_VERSION.to_string() {
+                    return Err(SnapshotError::UnsupportedVersion(header[1].to_owned()));
+                }
+            }
+            _ => return Err(SnapshotError::NotASnapshot),
+        }
+
+        let mut snapshot = Snapshot {
+            decimal_mode: None,
+            max_age: None,
+            auto_link: true,
+            trading_fees: vec![],
+            links: vec![],
+            transfer_costs: vec![],
+            next_node_id: 0,
+            nodes: vec![],
+            paths: vec![],
+        };
+        // node id -> index into `nodes`
+        let mut node_indices = HashMap::new();
+        for (i, line) in lines {
+            let invalid =
+                |reason: &dyn Display| SnapshotError::InvalidLine(i + 1, reason.to_string());
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.split_first() {
+                None => (),
+                Some((&"DECIMAL", rest)) => {
+                    snapshot.decimal_mode =
+                        validate_decimal_mode_input(rest).map_err(|e| invalid(&e))?
+                }
+                Some((&"TTL", rest)) => {
+                    snapshot.max_age = validate_ttl_input(rest).map_err(|e| invalid(&e))?
+                }
+                Some((&"AUTOLINK", rest)) => {
+                    snapshot.auto_link = validate_auto_link_input(rest).map_err(|e| invalid(&e))?
+                }
+                Some((&"FEE", rest)) => snapshot
+                    .trading_fees
+                    .push(validate_trading_fee_input(rest).map_err(|e| invalid(&e))?),
+                Some((&"LINK", rest)) => snapshot
+                    .links
+                    .push(validate_link_input(rest, true).map_err(|e| invalid(&e))?),
+                Some((&"UNLINK", rest)) => snapshot
+                    .links
+                    .push(validate_link_input(rest, false).map_err(|e| invalid(&e))?),
+                Some((&"TRANSFER", rest)) => snapshot
+                    .transfer_costs
+                    .push(validate_transfer_cost_input(rest).map_err(|e| invalid(&e))?),
+                Some((&"NEXT_NODE_ID", [id])) => {
+                    let id: usize = id.parse().map_err(|_| invalid(&"invalid id"))?;
+                    if snapshot.nodes.iter().any(|n| n.id >= id) {
+                        return Err(invalid(&"next node id already taken"));
+                    }
+                    snapshot.next_node_id = id;
+                }
+                // ids are never reused, so each one is below NEXT_NODE_ID
+                Some((&"NODE", [id, exchange, currency])) => {
+                    let id: usize = id.parse().map_err(|_| invalid(&"invalid id"))?;
+                    if id >= snapshot.next_node_id {
+                        return Err(invalid(&"node id not below the next node id"));
+                    }
+                    let index = snapshot.nodes.len();
+                    if node_indices.insert(id, index).is_some() {
+                        return Err(invalid(&"duplicate node id"));
+                    }
+                    snapshot
+                        .nodes
+                        .push(Rc::new(Node::new(exchange, currency, id, index)));
+                }
+                Some((&"PATH", [start, end, factor, state, ts, w, ew, gw, egw])) => {
+                    let node = |id: &str| {
+                        id.parse::<usize>()
+                            .ok()
+                            .and_then(|id| node_indices.get(&id))
+                            .map(|&i| Rc::clone(&snapshot.nodes[i]))
+                            .ok_or_else(|| invalid(&"unknown node"))
+                    };
+                    let mut path = Path::new(
+                        node(start)?,
+                        node(end)?,
+                        parse_weight(w).ok_or_else(|| invalid(&"invalid weight"))?,
+                        parse_exact_weight(ew).map_err(|e| invalid(&e))?,
+                        parse_timestamp(ts).ok_or_else(|| invalid(&"invalid timestamp"))?,
+                        parse_factor_name(factor).ok_or_else(|| invalid(&"unknown factor"))?,
+                    );
+                    path.gross_weight =
+                        parse_weight(gw).ok_or_else(|| invalid(&"invalid weight"))?;
+                    path.exact_gross_weight = parse_exact_weight(egw).map_err(|e| invalid(&e))?;
+                    path.halted = match *state {
+                        "halted" => true,
+                        "open" => false,
+                        _ => return Err(invalid(&"unknown state")),
+                    };
+                    path.history.clear();
+                    snapshot.paths.push(path);
+                }
+                Some((&"VERSION", [ts, gw, egw])) => {
+                    let version = PathVersion::new(
+                        parse_timestamp(ts).ok_or_else(|| invalid(&"invalid timestamp"))?,
+                        parse_weight(gw).ok_or_else(|| invalid(&"invalid weight"))?,
+                        parse_exact_weight(egw).map_err(|e| invalid(&e))?,
+                    );
+                    snapshot
+                        .paths
+                        .last_mut()
+                        .ok_or_else(|| invalid(&"version without a path"))?
+                        .record(version);
+                }
+                _ => return Err(invalid(&"unknown entry")),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+pub fn write_snapshot_file(args: &[&str], snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    match args {
+        [file] => fs::write(file, snapshot.to_text())
+            .map_err(|e| SnapshotError::Unwritable(e.to_string())),
+        _ => Err(SnapshotError::InvalidArgumentNumber),
+    }
+}
+
+pub fn read_snapshot_file(args: &[&str]) -> Result<Snapshot, SnapshotError> {
+    match args {
+        [file] => Snapshot::from_text(
+            &fs::read_to_string(file).map_err(|e| SnapshotError::Unreadable(e.to_string()))?,
+        ),
+        _ => Err(SnapshotError::InvalidArgumentNumber),
+    }
+}
+
+pub fn factor_name(factor: &Factor) -> &'static str {
+    match factor {
+        Factor::Forward => "forward",
+        Factor::Backward => "backward",
+        Factor::FilledUpForward => "filled-up-forward",
+        Factor::FilledUpBackward => "filled-up-backward",
+    }
+}
+
+fn parse_factor_name(name: &str) -> Option<Factor> {
+    match name {
+        "forward" => Some(Factor::Forward),
+        "backward" => Some(Factor::Backward),
+        "filled-up-forward" => Some(Factor::FilledUpForward),
+        "filled-up-backward" => Some(Factor::FilledUpBackward),
+        _ => None,
+    }
+}
+
+fn or_dash(exact_weight: Option<Decimal>) -> String {
+    exact_weight.map_or("-".to_owned(), |w| w.to_string())
+}
+
+fn parse_exact_weight(arg: &str) -> Result<Option<Decimal>, DecimalError> {
+    match arg {
+        "-" => Ok(None),
+        _ => arg.parse().map(Some),
+    }
+}
+
+fn parse_weight(arg: &str) -> Option<f64> {
+    arg.parse::<f64>()
+        .ok()
+        .filter(|w| w.is_finite() && *w > 0.0)
+}
+
+fn parse_timestamp(arg: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(arg)
+        .ok()
+        .map(|dt| DateTime::from_utc(dt.naive_utc(), Utc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_refuse_other_versions_and_bad_lines() {
+        assert_eq!(
+            SnapshotError::NotASnapshot,
+            Snapshot::from_text("BTC KRAKEN GDAX 0 0.0005\n")
+                .err()
+                .unwrap()
+        );
+        assert_eq!(
+            SnapshotError::UnsupportedVersion("2".to_owned()),
+            Snapshot::from_text("SNAPSHOT 2\n").err().unwrap()
+        );
+        assert_eq!(
+            SnapshotError::InvalidLine(4, "unknown node".to_owned()),
+            Snapshot::from_text(
+                "SNAPSHOT 1\nNEXT_NODE_ID 1\nNODE 0 KRAKEN BTC\nPATH 0 1 forward open \
+                 2017-11-01T09:42:23+00:00 1000 - 1000 -\n"
+            )
+            .err()
+            .unwrap()
+        );
+        assert_eq!(
+            SnapshotError::InvalidLine(4, "duplicate node id".to_owned()),
+            Snapshot::from_text(
+                "SNAPSHOT 1\nNEXT_NODE_ID 2\nNODE 0 KRAKEN BTC\nNODE 0 KRAKEN USD\n"
+            )
+            .err()
+            .unwrap()
+        );
+        assert_eq!(
+            SnapshotError::InvalidLine(4, "node id not below the next node id".to_owned()),
+            Snapshot::from_text(
+                "SNAPSHOT 1\nNEXT_NODE_ID 1\nNODE 0 KRAKEN BTC\nNODE 1 KRAKEN USD\n"
+            )
+            .err()
+            .unwrap()
+        );
+        assert_eq!(
+            SnapshotError::InvalidLine(4, "next node id already taken".to_owned()),
+            Snapshot::from_text("SNAPSHOT 1\nNEXT_NODE_ID 1\nNODE 0 KRAKEN BTC\nNEXT_NODE_ID 0\n")
+                .err()
+                .unwrap()
+        );
+        assert_eq!(
+            SnapshotError::InvalidLine(2, TtlValidationError::InvalidMaxAge.to_string()),
+            Snapshot::from_text("SNAPSHOT 1\nTTL -5\n").err().unwrap()
+        );
+    }
+}
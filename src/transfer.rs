@@ -0,0 +1,142 @@
+use crate::decimal::*;
+use crate::error::*;
+use std::collections::HashMap;
+
+// What moving a currency from one exchange to another costs: a share of the
+// amount plus a flat fee in the currency itself. Rates are quoted for one unit,
+// so the flat fee is charged on one unit as well, and a transfer edge carries
+// `1 - proportional_fee - flat_fee`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferCost {
+    pub proportional_fee: Decimal,
+    pub flat_fee: Decimal,
+}
+
+impl TransferCost {
+    pub fn new(
+        proportional_fee: Decimal,
+        flat_fee: Decimal,
+    ) -> Result<TransferCost, TransferCostValidationError> {
+        if proportional_fee.is_negative() {
+            return Err(TransferCostValidationError::InvalidProportionalFee);
+        }
+        if flat_fee.is_negative() {
+            return Err(TransferCostValidationError::InvalidFlatFee);
+        }
+        let cost = TransferCost {
+            proportional_fee,
+            flat_fee,
+        };
+        match cost.exact_weight() {
+            Some(w) if w.is_positive() => Ok(cost),
+            _ => Err(TransferCostValidationError::FeeTooHigh),
+        }
+    }
+
+    pub fn exact_weight(&self) -> Option<Decimal> {
+        Decimal::one()
+            .checked_sub(self.proportional_fee)?
+            .checked_sub(self.flat_fee)
+    }
+
+    pub fn weight(&self) -> f64 {
+        self.exact_weight().map_or(0.0, |w| w.to_f64())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferCostRequest {
+    pub currency: String,
+    pub from_exchange: String,
+    pub to_exchange: String,
+    pub cost: TransferCost,
+}
+
+impl TransferCostRequest {
+    pub fn new(
+        currency: String,
+        from_exchange: String,
+        to_exchange: String,
+        cost: TransferCost,
+    ) -> Self {
+        TransferCostRequest {
+            currency,
+            from_exchange,
+            to_exchange,
+            cost,
+        }
+    }
+}
+
+// Transfer costs keyed by (currency, from exchange, to exchange). Transfers
+// missing from the table are free.
+#[derive(Debug, Clone, Default)]
+pub struct TransferCosts {
+    costs: HashMap<(String, String, String), TransferCost>,
+}
+
+impl TransferCosts {
+    pub fn new() -> TransferCosts {
+        TransferCosts {
+            costs: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, request: &TransferCostRequest) {
+        self.costs.insert(
+            (
+                request.currency.clone(),
+                request.from_exchange.clone(),
+                request.to_exchange.clone(),
+            ),
+            request.cost,
+        );
+    }
+
+    pub fn get(
+        &self,
+        currency: &str,
+        from_exchange: &str,
+        to_exchange: &str,
+    ) -> Option<TransferCost> {
+        self.costs
+            .get(&(
+                currency.to_owned(),
+                from_exchange.to_owned(),
+                to_exchange.to_owned(),
+            ))
+            .cloned()
+    }
+
+    // Weight and exact weight of the transfer edge.
+    pub fn weight(
+        &self,
+        currency: &str,
+        from_exchange: &str,
+        to_exchange: &str,
+    ) -> (f64, Option<Decimal>) {
+        match self.get(currency, from_exchange, to_exchange) {
+            Some(cost) => (cost.weight(), cost.exact_weight()),
+            None => (1.0, Some(Decimal::one())),
+        }
+    }
+
+    // Every entry, sorted by currency and exchanges.
+    pub fn to_requests(&self) -> Vec<TransferCostRequest> {
+        let mut requests: Vec<TransferCostRequest> = self
+            .costs
+            .iter()
+            .map(|((currency, from, to), cost)| {
+                TransferCostRequest::new(currency.clone(), from.clone(), to.clone(), *cost)
+            })
+            .collect();
+        requests.sort_by(|a, b| {
+            (&a.currency, &a.from_exchange, &a.to_exchange).cmp(&(
+                &b.currency,
+                &b.from_exchange,
+                &b.to_exchange,
+            ))
+        });
+        requests
+    }
+}
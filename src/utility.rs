@@ -1,97 +1,404 @@
 extern crate chrono;
 extern crate colored;
 
+use crate::decimal::*;
 use crate::domain::*;
 use crate::error::*;
-use crate::graph::*;
+use crate::fees::*;
+use crate::links::*;
+use crate::transfer::*;
 use chrono::prelude::*;
+use std::fs;
 
 pub type PriceUpdateRequestValidationResult =
     Result<PriceUpdateRequest, PriceUpdateRequestValidationError>;
 pub type ExchangeRateRequestValidationResult =
     Result<ExchangeRateRequest, ExchangeRateRequestValidationError>;
+pub type DecimalModeValidationResult = Result<Option<DecimalMode>, DecimalModeValidationError>;
+pub type TradingFeeValidationResult = Result<TradingFeeRequest, TradingFeeValidationError>;
+pub type TtlValidationResult = Result<Option<chrono::Duration>, TtlValidationError>;
+pub type LinkValidationResult = Result<LinkRequest, LinkValidationError>;
+pub type TransferCostValidationResult = Result<TransferCostRequest, TransferCostValidationError>;
+pub type PairValidationResult = Result<PairRequest, PairValidationError>;
+pub type ExchangeValidationResult = Result<String, ExchangeValidationError>;
+pub type NodeValidationResult = Result<ExchangeCurrencyPair, NodeValidationError>;
+
+pub const MAX_HOPS_OPTION: &str = "--max-hops";
+pub const EXCLUDE_OPTION: &str = "--exclude";
+pub const ONLY_OPTION: &str = "--only";
 
 pub fn validate_exchange_rate_input(args: &[&str]) -> ExchangeRateRequestValidationResult {
-    if args.len() != 4 {
-        Err(ExchangeRateRequestValidationError::InvalidArgumentNumber)
-    } else {
-        Ok(ExchangeRateRequest::new(
-            args[0].trim().to_uppercase(),
-            args[1].trim().to_uppercase(),
-            args[2].trim().to_uppercase(),
-            args[3].trim().to_uppercase(),
+    let mut positional = vec![];
+    let mut max_hops = None;
+    let mut excluded_exchanges = vec![];
+    let mut only_exchanges: Option<Vec<String>> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.trim() {
+            MAX_HOPS_OPTION => {
+                max_hops = Some(
+                    iter.next()
+                        .and_then(|h| parse_count(h))
+                        .ok_or(ExchangeRateRequestValidationError::InvalidMaxHops)?,
+                )
+            }
+            option @ EXCLUDE_OPTION => {
+                excluded_exchanges.extend(parse_exchange_list(option, iter.next().copied())?)
+            }
+            option @ ONLY_OPTION => only_exchanges
+                .get_or_insert_with(Vec::new)
+                .extend(parse_exchange_list(option, iter.next().copied())?),
+            option if option.starts_with("--") => {
+                return Err(ExchangeRateRequestValidationError::UnknownOption(
+                    option.to_owned(),
+                ))
+            }
+            _ => positional.push(*arg),
+        }
+    }
+
+    if positional.len() != 4 && positional.len() != 5 {
+        return Err(ExchangeRateRequestValidationError::InvalidArgumentNumber);
+    }
+    let route_count = match positional.get(4) {
+        Some(count) => {
+            parse_count(count).ok_or(ExchangeRateRequestValidationError::InvalidRouteCount)?
+        }
+        None => 1,
+    };
+    Ok(ExchangeRateRequest::new(
+        positional[0].trim().to_uppercase(),
+        positional[1].trim().to_uppercase(),
+        positional[2].trim().to_uppercase(),
+        positional[3].trim().to_uppercase(),
+    )
+    .with_route_count(route_count)
+    .with_max_hops(max_hops)
+    .with_exchange_filters(excluded_exchanges, only_exchanges))
+}
+
+// A rate request against the quotes known at `at`, an RFC 3339 time.
+pub fn validate_point_in_time_rate_input(
+    at: &str,
+    args: &[&str],
+) -> ExchangeRateRequestValidationResult {
+    let at = DateTime::parse_from_rfc3339(at.trim())
+        .map_err(|_| ExchangeRateRequestValidationError::InvalidTimestamp)?;
+    validate_exchange_rate_input(args)
+        .map(|r| r.with_at(Some(DateTime::from_utc(at.naive_utc(), chrono::Utc))))
+}
+
+// An exchange and two currencies, as in `KRAKEN BTC USD`.
+pub fn validate_pair_input(args: &[&str]) -> PairValidationResult {
+    if args.len() != 3 {
+        return Err(PairValidationError::InvalidArgumentNumber);
+    }
+    let source_currency = args[1].trim().to_uppercase();
+    let destination_currency = args[2].trim().to_uppercase();
+    if source_currency == destination_currency {
+        return Err(PairValidationError::SameCurrency);
+    }
+    Ok(PairRequest::new(
+        args[0].trim().to_uppercase(),
+        source_currency,
+        destination_currency,
+    ))
+}
+
+pub fn validate_exchange_input(args: &[&str]) -> ExchangeValidationResult {
+    match args {
+        [exchange] => Ok(exchange.trim().to_uppercase()),
+        _ => Err(ExchangeValidationError::InvalidArgumentNumber),
+    }
+}
+
+pub fn validate_node_input(args: &[&str]) -> NodeValidationResult {
+    match args {
+        [exchange, currency] => Ok(ExchangeCurrencyPair::new(
+            exchange.trim().to_uppercase(),
+            currency.trim().to_uppercase(),
+        )),
+        _ => Err(NodeValidationError::InvalidArgumentNumber),
+    }
+}
+
+// Comma-separated exchange names, as in `KRAKEN,GDAX`.
+fn parse_exchange_list(
+    option: &str,
+    arg: Option<&str>,
+) -> Result<Vec<String>, ExchangeRateRequestValidationError> {
+    let exchanges: Vec<String> = arg
+        .map(|a| a.split(',').map(|e| e.trim().to_uppercase()).collect())
+        .unwrap_or_default();
+    if exchanges.is_empty()
+        || exchanges
+            .iter()
+            .any(|e| e.is_empty() || e.starts_with("--"))
+    {
+        Err(ExchangeRateRequestValidationError::InvalidExchangeList(
+            option.to_owned(),
         ))
+    } else {
+        Ok(exchanges)
+    }
+}
+
+// A whole number above 0.
+fn parse_count(arg: &str) -> Option<usize> {
+    arg.trim().parse::<usize>().ok().filter(|&c| c > 0)
+}
+
+pub fn validate_decimal_mode_input(args: &[&str]) -> DecimalModeValidationResult {
+    match args {
+        [off] if off.trim().to_lowercase() == "off" => Ok(None),
+        [scale] | [scale, _] => {
+            let scale = scale
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&s| s <= MAX_SCALE)
+                .ok_or(DecimalModeValidationError::ScaleOutOfRange)?;
+            let rounding = match args.get(1) {
+                Some(r) => r.parse::<Rounding>()?,
+                None => Rounding::HalfEven,
+            };
+            Ok(Some(DecimalMode::new(scale, rounding)))
+        }
+        _ => Err(DecimalModeValidationError::InvalidArgumentNumber),
     }
 }
 
-pub fn validate_price_update_input(args: &[&str], g: &Graph) -> PriceUpdateRequestValidationResult {
+pub fn validate_trading_fee_input(args: &[&str]) -> TradingFeeValidationResult {
+    let pair = match args.len() {
+        2 => None,
+        4 => {
+            let (a, b) = (args[1].trim().to_uppercase(), args[2].trim().to_uppercase());
+            if a == b {
+                return Err(TradingFeeValidationError::SameCurrency);
+            }
+            Some((a, b))
+        }
+        _ => return Err(TradingFeeValidationError::InvalidArgumentNumber),
+    };
+    let fee = args[args.len() - 1]
+        .parse()
+        .map_err(|_| TradingFeeValidationError::InvalidFee)?;
+    TradingFeeRequest::new(args[0].trim().to_uppercase(), pair, fee)
+}
+
+pub fn validate_ttl_input(args: &[&str]) -> TtlValidationResult {
+    match args {
+        [off] if off.trim().to_lowercase() == "off" => Ok(None),
+        [seconds] => seconds
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|&s| s > 0 && s <= i64::from(u32::MAX))
+            .map(|s| Some(chrono::Duration::seconds(s)))
+            .ok_or(TtlValidationError::InvalidMaxAge),
+        _ => Err(TtlValidationError::InvalidArgumentNumber),
+    }
+}
+
+pub fn validate_link_input(args: &[&str], linked: bool) -> LinkValidationResult {
+    if args.len() != 3 {
+        return Err(LinkValidationError::InvalidArgumentNumber);
+    }
+    let (a, b) = (args[0].trim().to_uppercase(), args[1].trim().to_uppercase());
+    if a == b {
+        return Err(LinkValidationError::SameExchange);
+    }
+    Ok(LinkRequest::new(
+        args[2].trim().to_uppercase(),
+        (a, b),
+        linked,
+    ))
+}
+
+pub fn validate_auto_link_input(args: &[&str]) -> Result<bool, LinkValidationError> {
+    match args {
+        [mode] if mode.trim().to_lowercase() == "on" => Ok(true),
+        [mode] if mode.trim().to_lowercase() == "off" => Ok(false),
+        _ => Err(LinkValidationError::InvalidAutoLinkMode),
+    }
+}
+
+pub fn validate_transfer_cost_input(args: &[&str]) -> TransferCostValidationResult {
+    if args.len() != 5 {
+        return Err(TransferCostValidationError::InvalidArgumentNumber);
+    }
+    let from_exchange = args[1].trim().to_uppercase();
+    let to_exchange = args[2].trim().to_uppercase();
+    if from_exchange == to_exchange {
+        return Err(TransferCostValidationError::SameExchange);
+    }
+    let proportional_fee = args[3]
+        .parse()
+        .map_err(|_| TransferCostValidationError::InvalidProportionalFee)?;
+    let flat_fee = args[4]
+        .parse()
+        .map_err(|_| TransferCostValidationError::InvalidFlatFee)?;
+    Ok(TransferCostRequest::new(
+        args[0].trim().to_uppercase(),
+        from_exchange,
+        to_exchange,
+        TransferCost::new(proportional_fee, flat_fee)?,
+    ))
+}
+
+// Reads a transfer cost file: one `CURRENCY FROM TO PROPORTIONAL FLAT` entry per
+// line, as typed after `:t`. Blank lines and lines starting with `#` are skipped.
+// Nothing is returned unless every entry is valid.
+pub fn read_transfer_cost_file(
+    args: &[&str],
+) -> Result<Vec<TransferCostRequest>, TransferCostFileError> {
+    if args.len() != 1 {
+        return Err(TransferCostFileError::InvalidArgumentNumber);
+    }
+    let content = fs::read_to_string(args[0])
+        .map_err(|e| TransferCostFileError::Unreadable(e.to_string()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|(i, line)| {
+            let args: Vec<&str> = line.split_whitespace().collect();
+            validate_transfer_cost_input(&args)
+                .map_err(|e| TransferCostFileError::InvalidLine(i + 1, e))
+        })
+        .collect()
+}
+
+pub const BATCH_OPTION: &str = "--batch";
+pub const PROTOCOL_OPTION: &str = "--protocol";
+pub const WAL_OPTION: &str = "--wal";
+pub const SNAPSHOT_OPTION: &str = "--snapshot";
+pub const FORMAT_OPTION: &str = "--format";
+
+pub fn validate_command_line(args: &[&str]) -> Result<CommandLineOptions, CommandLineError> {
+    let mut options = CommandLineOptions::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let file = match *arg {
+            BATCH_OPTION => {
+                options.batch = true;
+                continue;
+            }
+            PROTOCOL_OPTION => {
+                options.protocol = true;
+                continue;
+            }
+            FORMAT_OPTION => {
+                options.format = match iter.next() {
+                    Some(&"text") => OutputFormat::Text,
+                    Some(&"json") => OutputFormat::Json,
+                    Some(format) => {
+                        return Err(CommandLineError::UnknownFormat(format.to_string()))
+                    }
+                    None => return Err(CommandLineError::MissingValue(arg.to_string())),
+                };
+                continue;
+            }
+            WAL_OPTION => &mut options.wal_file,
+            SNAPSHOT_OPTION => &mut options.snapshot_file,
+            _ => return Err(CommandLineError::UnknownOption(arg.to_string())),
+        };
+        *file = Some(
+            iter.next()
+                .map(|f| f.to_string())
+                .ok_or_else(|| CommandLineError::MissingValue(arg.to_string()))?,
+        );
+    }
+    Ok(options)
+}
+
+pub const CSV_OPTION: &str = "--csv";
+
+// Currencies to keep, and the file to export to as CSV if `--csv` is given.
+pub fn validate_rate_matrix_input(
+    args: &[&str],
+) -> Result<(Vec<String>, Option<String>), RateMatrixError> {
+    let mut currencies = vec![];
+    let mut csv_file = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.trim() {
+            CSV_OPTION => {
+                csv_file = Some(
+                    iter.next()
+                        .map(|f| f.trim().to_owned())
+                        .ok_or(RateMatrixError::MissingFileName)?,
+                )
+            }
+            currency => currencies.push(currency.to_uppercase()),
+        }
+    }
+    Ok((currencies, csv_file))
+}
+
+pub fn write_rate_matrix_csv(file: &str, matrix: &RateMatrix) -> Result<(), RateMatrixError> {
+    fs::write(file, matrix.to_csv()).map_err(|e| RateMatrixError::Unwritable(e.to_string()))
+}
+
+pub fn validate_price_update_input(args: &[&str]) -> PriceUpdateRequestValidationResult {
     if args.len() != 6 {
         Err(PriceUpdateRequestValidationError::InvalidArgumentNumber)
     } else if args[2].trim().to_uppercase() == args[3].trim().to_uppercase() {
         Err(PriceUpdateRequestValidationError::SameSourceDestinationCurrency)
     } else {
         DateTime::parse_from_rfc3339(args[0].trim())
-        .map_err(|_| PriceUpdateRequestValidationError::InvalidTimestamp)
-        .and_then(|dt| {
-            args[4]
-                .trim()
-                .parse::<f32>()
-                .map_err(|_| PriceUpdateRequestValidationError::InvalidForwardfactor)
-                .map(|x| {
-                    if x <= 0.0 {
-                        Err(PriceUpdateRequestValidationError::InvalidForwardfactor)
-                    } else {
-                        Ok(x)
-                    }
-                })
-                .and_then(|rff| {
-                    args[5]
-                        .trim()
-                        .parse::<f32>()
-                        .map_err(|_| PriceUpdateRequestValidationError::InvalidBackwardfactor)
-                        .map(|x| {
-                            if x <= 0.0 {
-                                Err(PriceUpdateRequestValidationError::InvalidBackwardfactor)
-                            } else {
-                                Ok(x)
-                            }
-                        })
-                        .and_then(|rbf| {
-                            let ff = rff.unwrap();
-                            let bf = rbf.unwrap();
-
-                            let existing_paths = g.get_forward_backward_factor_of_existing_paths();
-                            let ff_over_one = existing_paths.1.iter().any(|x| x * ff > 1.0);
-                            let bb_over_one = existing_paths.0.iter().any(|x| x * bf > 1.0);
-
-                            if ff_over_one || bb_over_one{
-                                Err(PriceUpdateRequestValidationError::CrossForwardBackwardFactorMultiplyError)
-                            }
-                            else if ff * bf > 1.0 {
-                                Err(PriceUpdateRequestValidationError::ForwardBackwardFactorMultiplyError)
-                            }                            
-                            else {
-                                Ok(PriceUpdateRequest::new(
-                                    DateTime::from_utc(dt.naive_utc(), chrono::Utc),
-                                    args[1].trim().to_uppercase(),
-                                    args[2].trim().to_uppercase(),
-                                    args[3].trim().to_uppercase(),
-                                    ff,
-                                    bf,
-                                ))
-                            }
-                        })
-                })
-        })
+            .map_err(|_| PriceUpdateRequestValidationError::InvalidTimestamp)
+            .and_then(|dt| {
+                let ff = parse_factor(args[4])
+                    .ok_or(PriceUpdateRequestValidationError::InvalidForwardfactor)?;
+                let bf = parse_factor(args[5])
+                    .ok_or(PriceUpdateRequestValidationError::InvalidBackwardfactor)?;
+                let exact_ff = parse_exact_factor(
+                    args[4],
+                    PriceUpdateRequestValidationError::InvalidForwardfactor,
+                )?;
+                let exact_bf = parse_exact_factor(
+                    args[5],
+                    PriceUpdateRequestValidationError::InvalidBackwardfactor,
+                )?;
+                Ok(PriceUpdateRequest::new(
+                    DateTime::from_utc(dt.naive_utc(), chrono::Utc),
+                    args[1].trim().to_uppercase(),
+                    args[2].trim().to_uppercase(),
+                    args[3].trim().to_uppercase(),
+                    ff,
+                    bf,
+                )
+                .with_exact_factors(Some(exact_ff), Some(exact_bf)))
+            })
     }
 }
 
+// Any positive, finite factor is accepted; profitable cycles are reported by the graph.
+fn parse_factor(arg: &str) -> Option<f64> {
+    arg.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|x| *x > 0.0 && x.is_finite())
+}
+
+// The factor as typed, for decimal mode; one with more digits than a decimal
+// holds is refused rather than kept inexact.
+fn parse_exact_factor(
+    arg: &str,
+    invalid: PriceUpdateRequestValidationError,
+) -> Result<Decimal, PriceUpdateRequestValidationError> {
+    arg.trim().parse().map_err(|e| match e {
+        DecimalError::Overflow => PriceUpdateRequestValidationError::FactorTooPrecise,
+        DecimalError::InvalidFormat => invalid,
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
     fn validate_priceupdaterequest_with_valid_data() {
-        let g = Graph::new();
         const REQ: &[&str] = &[
             " 2017-11-01T09:42:23+00:00 ",
             " KrAKEN",
@@ -100,7 +407,7 @@ mod test {
             " 1000.0 ",
             " 0.0009 ",
         ];
-        let result = validate_price_update_input(&REQ, &g);
+        let result = validate_price_update_input(REQ);
 
         let dt = DateTime::parse_from_rfc3339(REQ[0].trim()).unwrap();
         let ts = DateTime::from_utc(dt.naive_utc(), chrono::Utc);
@@ -109,15 +416,14 @@ mod test {
             REQ[1].trim().to_uppercase(),
             REQ[2].trim().to_uppercase(),
             REQ[3].trim().to_uppercase(),
-            REQ[4].trim().parse::<f32>().unwrap(),
-            REQ[5].trim().parse::<f32>().unwrap(),
+            REQ[4].trim().parse::<f64>().unwrap(),
+            REQ[5].trim().parse::<f64>().unwrap(),
         );
         assert_eq!(expected, result.unwrap());
     }
 
     #[test]
     fn should_throw_invalid_argument_number_error() {
-        let g = Graph::new();
         const REQ: &[&str] = &[
             " 2017-11-01T09:42:23+00:00 ",
             " KrAKEN",
@@ -127,14 +433,13 @@ mod test {
             " 0.0009 ",
         ];
         assert_eq!(
-            validate_price_update_input(&REQ, &g).unwrap_err(),
+            validate_price_update_input(REQ).unwrap_err(),
             PriceUpdateRequestValidationError::InvalidArgumentNumber
         );
     }
 
     #[test]
     fn should_throw_same_source_destination_currency_error() {
-        let g = Graph::new();
         const REQ: &[&str] = &[
             " 2017-11-01T09:42:23+00:00 ",
             " KrAKEN",
@@ -144,14 +449,13 @@ mod test {
             " 0.0009 ",
         ];
         assert_eq!(
-            validate_price_update_input(&REQ, &g).unwrap_err(),
+            validate_price_update_input(REQ).unwrap_err(),
             PriceUpdateRequestValidationError::SameSourceDestinationCurrency
         );
     }
 
     #[test]
     fn should_throw_invalid_timestamp_error() {
-        let g = Graph::new();
         const REQ: &[&str] = &[
             " 2017-11-01T09: ssabc42:23+00:00 ",
             " KrAKEN",
@@ -161,14 +465,13 @@ mod test {
             " 0.0009 ",
         ];
         assert_eq!(
-            validate_price_update_input(&REQ, &g).unwrap_err(),
+            validate_price_update_input(REQ).unwrap_err(),
             PriceUpdateRequestValidationError::InvalidTimestamp
         );
     }
 
     #[test]
     fn should_throw_invalid_forward_factor_error() {
-        let  g = Graph::new();
         const REQ: &[&str] = &[
             " 2017-11-01T09:42:23+00:00 ",
             " KrAKEN",
@@ -178,14 +481,13 @@ mod test {
             " 0.0009 ",
         ];
         assert_eq!(
-            validate_price_update_input(&REQ, &g).unwrap_err(),
+            validate_price_update_input(REQ).unwrap_err(),
             PriceUpdateRequestValidationError::InvalidForwardfactor
         );
     }
 
     #[test]
     fn should_throw_invalid_backward_factor_error() {
-        let  g = Graph::new();
         const REQ: &[&str] = &[
             " 2017-11-01T09:42:23+00:00 ",
             " KrAKEN",
@@ -195,14 +497,376 @@ mod test {
             " 0.xx0009 ",
         ];
         assert_eq!(
-            validate_price_update_input(&REQ, &g).unwrap_err(),
+            validate_price_update_input(REQ).unwrap_err(),
             PriceUpdateRequestValidationError::InvalidBackwardfactor
         );
     }
 
     #[test]
-    fn should_throw_forward_backward_factor_multiply_error() {
-        let g = Graph::new();
+    fn validate_priceupdaterequest_keeps_exact_factors() {
+        const REQ: &[&str] = &[
+            "2017-11-01T09:42:23+00:00",
+            "KRAKEN",
+            "BTC",
+            "USD",
+            "1000.00000000000000000001",
+            "0.0009",
+        ];
+        let result = validate_price_update_input(REQ).unwrap();
+        assert_eq!(1000.0, result.forward_factor);
+        assert_eq!(
+            "1000.00000000000000000001",
+            result.exact_forward_factor.unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn validate_exchange_rate_request_route_count() {
+        let result = validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd"]).unwrap();
+        assert_eq!(1, result.route_count);
+        let result =
+            validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd", " 5 "]).unwrap();
+        assert_eq!("GDAX", result.destination_exchange);
+        assert_eq!(5, result.route_count);
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidRouteCount,
+            validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd", "0"]).unwrap_err()
+        );
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidArgumentNumber,
+            validate_exchange_rate_input(&["kraken", "btc", "gdax"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_exchange_rate_request_max_hops() {
+        let result = validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd"]).unwrap();
+        assert_eq!(None, result.max_hops);
+        let result =
+            validate_exchange_rate_input(&["kraken", "btc", "--max-hops", "2", "gdax", "usd", "3"])
+                .unwrap();
+        assert_eq!(Some(2), result.max_hops);
+        assert_eq!(3, result.route_count);
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidMaxHops,
+            validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd", "--max-hops"])
+                .unwrap_err()
+        );
+        assert_eq!(
+            ExchangeRateRequestValidationError::UnknownOption("--hops".to_owned()),
+            validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd", "--hops", "2"])
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_exchange_rate_request_exchange_filters() {
+        let result = validate_exchange_rate_input(&[
+            "kraken",
+            "btc",
+            "gdax",
+            "usd",
+            "--exclude",
+            "bittrex",
+            "--only",
+            "kraken,gdax",
+            "--exclude",
+            "binance",
+        ])
+        .unwrap();
+        assert_eq!(vec!["BITTREX", "BINANCE"], result.excluded_exchanges);
+        assert_eq!(
+            Some(vec!["KRAKEN".to_owned(), "GDAX".to_owned()]),
+            result.only_exchanges
+        );
+        assert!(result.allows_exchange("KRAKEN"));
+        assert!(!result.allows_exchange("BITTREX"));
+        assert!(!result.allows_exchange("POLONIEX"));
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidExchangeList("--only".to_owned()),
+            validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd", "--only", "kraken,"])
+                .unwrap_err()
+        );
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidExchangeList("--exclude".to_owned()),
+            validate_exchange_rate_input(&["kraken", "btc", "gdax", "usd", "--exclude"])
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_point_in_time_rate_request() {
+        let result = validate_point_in_time_rate_input(
+            "2017-11-01T10:00:00+01:00",
+            &["kraken", "btc", "gdax", "usd", "2"],
+        )
+        .unwrap();
+        assert_eq!(Some(Utc.ymd(2017, 11, 1).and_hms(9, 0, 0)), result.at);
+        assert_eq!(2, result.route_count);
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidTimestamp,
+            validate_point_in_time_rate_input("yesterday", &["kraken", "btc", "gdax", "usd"])
+                .unwrap_err()
+        );
+        assert_eq!(
+            ExchangeRateRequestValidationError::InvalidArgumentNumber,
+            validate_point_in_time_rate_input("2017-11-01T10:00:00Z", &["kraken", "btc"])
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_pair_and_exchange() {
+        assert_eq!(
+            PairRequest::new("KRAKEN".to_owned(), "BTC".to_owned(), "USD".to_owned()),
+            validate_pair_input(&["kraken", " btc", "usd "]).unwrap()
+        );
+        assert_eq!(
+            PairValidationError::SameCurrency,
+            validate_pair_input(&["kraken", "btc", "BTC"]).unwrap_err()
+        );
+        assert_eq!(
+            PairValidationError::InvalidArgumentNumber,
+            validate_pair_input(&["kraken", "btc"]).unwrap_err()
+        );
+        assert_eq!("KRAKEN", validate_exchange_input(&["kraken"]).unwrap());
+        assert_eq!(
+            ExchangeCurrencyPair::new("KRAKEN".to_owned(), "BTC".to_owned()),
+            validate_node_input(&["kraken", "btc"]).unwrap()
+        );
+        assert_eq!(
+            NodeValidationError::InvalidArgumentNumber,
+            validate_node_input(&["kraken"]).unwrap_err()
+        );
+        assert_eq!(
+            ExchangeValidationError::InvalidArgumentNumber,
+            validate_exchange_input(&["kraken", "gdax"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_rate_matrix() {
+        assert_eq!(
+            (vec!["BTC".to_owned(), "USD".to_owned()], None),
+            validate_rate_matrix_input(&["btc", "usd"]).unwrap()
+        );
+        assert_eq!(
+            (vec![], Some("rates.csv".to_owned())),
+            validate_rate_matrix_input(&["--csv", "rates.csv"]).unwrap()
+        );
+        assert_eq!(
+            RateMatrixError::MissingFileName,
+            validate_rate_matrix_input(&["btc", "--csv"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_command_line_options() {
+        assert_eq!(
+            CommandLineOptions {
+                batch: true,
+                protocol: false,
+                wal_file: Some("prices.log".to_owned()),
+                snapshot_file: Some("prices.snap".to_owned()),
+                format: OutputFormat::Json,
+            },
+            validate_command_line(&[
+                "--wal",
+                "prices.log",
+                "--batch",
+                "--snapshot",
+                "prices.snap",
+                "--format",
+                "json"
+            ])
+            .unwrap()
+        );
+        assert_eq!(
+            CommandLineOptions::default(),
+            validate_command_line(&[]).unwrap()
+        );
+        assert!(validate_command_line(&["--protocol"]).unwrap().protocol);
+        assert_eq!(
+            CommandLineError::MissingValue("--wal".to_owned()),
+            validate_command_line(&["--wal"]).unwrap_err()
+        );
+        assert_eq!(
+            CommandLineError::UnknownFormat("xml".to_owned()),
+            validate_command_line(&["--format", "xml"]).unwrap_err()
+        );
+        assert_eq!(
+            CommandLineError::UnknownOption("--verbose".to_owned()),
+            validate_command_line(&["--verbose"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_trading_fee() {
+        let result = validate_trading_fee_input(&["kraken", "0.0026"]).unwrap();
+        assert_eq!("KRAKEN", result.exchange);
+        assert_eq!(None, result.pair);
+        assert_eq!("0.0026", result.fee.to_string());
+        let result = validate_trading_fee_input(&["kraken", "btc", "usd", "0"]).unwrap();
+        assert_eq!(Some(("BTC".to_owned(), "USD".to_owned())), result.pair);
+
+        let cases: &[(&[&str], TradingFeeValidationError)] = &[
+            (
+                &["kraken"],
+                TradingFeeValidationError::InvalidArgumentNumber,
+            ),
+            (
+                &["kraken", "btc", "0.1"],
+                TradingFeeValidationError::InvalidArgumentNumber,
+            ),
+            (
+                &["kraken", "btc", "BTC", "0.1"],
+                TradingFeeValidationError::SameCurrency,
+            ),
+            (&["kraken", "1"], TradingFeeValidationError::InvalidFee),
+            (&["kraken", "-0.1"], TradingFeeValidationError::InvalidFee),
+            (&["kraken", "abc"], TradingFeeValidationError::InvalidFee),
+        ];
+        for (args, error) in cases.iter() {
+            assert_eq!(*error, validate_trading_fee_input(args).unwrap_err());
+        }
+    }
+
+    #[test]
+    fn validate_ttl() {
+        assert_eq!(
+            Ok(Some(chrono::Duration::seconds(90))),
+            validate_ttl_input(&["90"])
+        );
+        assert_eq!(Ok(None), validate_ttl_input(&["Off"]));
+        assert_eq!(
+            TtlValidationError::InvalidMaxAge,
+            validate_ttl_input(&["0"]).unwrap_err()
+        );
+        assert_eq!(
+            TtlValidationError::InvalidMaxAge,
+            validate_ttl_input(&["1.5"]).unwrap_err()
+        );
+        assert_eq!(
+            TtlValidationError::InvalidArgumentNumber,
+            validate_ttl_input(&[]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_link() {
+        assert_eq!(
+            Ok(LinkRequest::new(
+                "BTC".to_owned(),
+                ("KRAKEN".to_owned(), "GDAX".to_owned()),
+                false
+            )),
+            validate_link_input(&["kraken", "gdax", "btc"], false)
+        );
+        assert_eq!(
+            LinkValidationError::SameExchange,
+            validate_link_input(&["kraken", "KRAKEN", "btc"], true).unwrap_err()
+        );
+        assert_eq!(
+            LinkValidationError::InvalidArgumentNumber,
+            validate_link_input(&["kraken", "gdax"], true).unwrap_err()
+        );
+        assert_eq!(Ok(false), validate_auto_link_input(&["OFF"]));
+        assert_eq!(
+            LinkValidationError::InvalidAutoLinkMode,
+            validate_auto_link_input(&["maybe"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn validate_transfer_cost() {
+        let result = validate_transfer_cost_input(&["btc", "kraken", "gdax", "0.001", "0.0005"]);
+        let expected = TransferCostRequest::new(
+            "BTC".to_owned(),
+            "KRAKEN".to_owned(),
+            "GDAX".to_owned(),
+            TransferCost::new("0.001".parse().unwrap(), "0.0005".parse().unwrap()).unwrap(),
+        );
+        assert_eq!(expected, result.unwrap());
+        assert_eq!("0.9985", expected.cost.exact_weight().unwrap().to_string());
+
+        let cases: &[(&[&str], TransferCostValidationError)] = &[
+            (
+                &["btc", "kraken", "gdax", "0.001"],
+                TransferCostValidationError::InvalidArgumentNumber,
+            ),
+            (
+                &["btc", "kraken", "KRAKEN", "0.001", "0"],
+                TransferCostValidationError::SameExchange,
+            ),
+            (
+                &["btc", "kraken", "gdax", "-0.1", "0"],
+                TransferCostValidationError::InvalidProportionalFee,
+            ),
+            (
+                &["btc", "kraken", "gdax", "0.1", "x"],
+                TransferCostValidationError::InvalidFlatFee,
+            ),
+            (
+                &["btc", "kraken", "gdax", "0.5", "0.5"],
+                TransferCostValidationError::FeeTooHigh,
+            ),
+        ];
+        for (args, error) in cases.iter() {
+            assert_eq!(*error, validate_transfer_cost_input(args).unwrap_err());
+        }
+    }
+
+    #[test]
+    fn read_transfer_costs_from_file() {
+        let path = std::env::temp_dir().join(format!("transfer-costs-{}.txt", std::process::id()));
+        let path_arg = path.to_str().unwrap();
+
+        fs::write(
+            &path,
+            "# withdrawal fees\n\nBTC KRAKEN GDAX 0 0.0005\nETH GDAX KRAKEN 0.001 0\n",
+        )
+        .unwrap();
+        let requests = read_transfer_cost_file(&[path_arg]).unwrap();
+        assert_eq!(2, requests.len());
+        assert_eq!("ETH", requests[1].currency);
+
+        fs::write(&path, "BTC KRAKEN GDAX 0 0.0005\nBTC KRAKEN GDAX 2 0\n").unwrap();
+        assert_eq!(
+            TransferCostFileError::InvalidLine(2, TransferCostValidationError::FeeTooHigh),
+            read_transfer_cost_file(&[path_arg]).unwrap_err()
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(read_transfer_cost_file(&[path_arg]).is_err());
+    }
+
+    #[test]
+    fn validate_decimal_mode() {
+        assert_eq!(
+            Ok(Some(DecimalMode::new(8, Rounding::HalfEven))),
+            validate_decimal_mode_input(&["8"])
+        );
+        assert_eq!(
+            Ok(Some(DecimalMode::new(2, Rounding::Down))),
+            validate_decimal_mode_input(&["2", "DOWN"])
+        );
+        assert_eq!(Ok(None), validate_decimal_mode_input(&["off"]));
+        assert_eq!(
+            DecimalModeValidationError::ScaleOutOfRange,
+            validate_decimal_mode_input(&["29"]).unwrap_err()
+        );
+        assert_eq!(
+            DecimalModeValidationError::UnknownRounding,
+            validate_decimal_mode_input(&["2", "nearest"]).unwrap_err()
+        );
+        assert_eq!(
+            DecimalModeValidationError::InvalidArgumentNumber,
+            validate_decimal_mode_input(&[]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_accept_factors_whose_product_exceeds_one() {
         const REQ: &[&str] = &[
             " 2017-11-01T09:42:23+00:00 ",
             " KrAKEN",
@@ -211,9 +875,40 @@ mod test {
             " 1000 ",
             " 1.1 ",
         ];
+        let result = validate_price_update_input(REQ).unwrap();
+        assert_eq!(1000.0, result.forward_factor);
+        assert_eq!(1.1, result.backward_factor);
+    }
+
+    #[test]
+    fn should_throw_invalid_factor_error_for_non_positive_factors() {
+        const REQ: &[&str] = &[
+            " 2017-11-01T09:42:23+00:00 ",
+            " KrAKEN",
+            " BtC",
+            " Usd",
+            " -1000 ",
+            " 0.0009 ",
+        ];
+        assert_eq!(
+            validate_price_update_input(REQ).unwrap_err(),
+            PriceUpdateRequestValidationError::InvalidForwardfactor
+        );
+    }
+
+    #[test]
+    fn should_throw_factor_too_precise_error_for_factors_a_decimal_cannot_hold() {
+        const REQ: &[&str] = &[
+            " 2017-11-01T09:42:23+00:00 ",
+            " KrAKEN",
+            " BtC",
+            " Usd",
+            " 1000.000000000000000000000000000000000000001 ",
+            " 0.0009 ",
+        ];
         assert_eq!(
-            validate_price_update_input(&REQ, &g).unwrap_err(),
-            PriceUpdateRequestValidationError::ForwardBackwardFactorMultiplyError
+            validate_price_update_input(REQ).unwrap_err(),
+            PriceUpdateRequestValidationError::FactorTooPrecise
         );
     }
 }
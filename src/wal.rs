@@ -0,0 +1,202 @@
+use crate::decimal::*;
+use crate::domain::*;
+use crate::error::*;
+use crate::snapshot::*;
+use crate::utility::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+// Append-only log of the changes made to the graph, one per line: price
+// updates in the `:u` argument format, and the other commands that change the
+// graph as typed, `:` included. Each update the graph accepts is written, and
+// synced, before the graph applies it, and each command once it succeeded, so replaying the log
+// over the snapshot it was started from rebuilds the graph. A record only counts
+// once its newline is on disk.
+pub struct WriteAheadLog {
+    file: File,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    PriceUpdate(PriceUpdateRequest),
+    // e.g. `:halt KRAKEN BTC USD`, checked when it is replayed
+    Command(String),
+}
+
+impl WriteAheadLog {
+    // Opens the log for appending, creating it if needed, and returns the
+    // records already in it. A last record cut short is dropped from the file.
+    pub fn open(path: &str) -> Result<(WriteAheadLog, Vec<Record>), WalError> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(WalError::Unreadable(e.to_string())),
+        };
+        let complete = content
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let text = String::from_utf8(content[..complete].to_vec())
+            .map_err(|e| WalError::Unreadable(e.to_string()))?;
+        let records = parse_records(&text)?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|f| f.set_len(complete as u64).map(|_| f))
+            .map_err(|e| WalError::Unwritable(e.to_string()))?;
+        Ok((WriteAheadLog { file }, records))
+    }
+
+    pub fn append(&mut self, request: &PriceUpdateRequest) -> Result<(), WalError> {
+        self.write_line(&format_record(request))
+    }
+
+    pub fn append_command(&mut self, command: &str) -> Result<(), WalError> {
+        self.write_line(command)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), WalError> {
+        self.file
+            .write_all(format!("{}\n", line).as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| WalError::Unwritable(e.to_string()))
+    }
+
+    // Writes the snapshot, replacing the old one only once it is complete and on
+    // disk, then empties the log it now covers. The directory is synced first so
+    // the rename can't be lost while the emptied log survives.
+    pub fn compact_into(&mut self, path: &str, snapshot: &Snapshot) -> Result<(), WalError> {
+        let temporary = format!("{}.tmp", path);
+        let directory = match std::path::Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+        File::create(&temporary)
+            .and_then(|mut f| {
+                f.write_all(snapshot.to_text().as_bytes())
+                    .and_then(|_| f.sync_all())
+            })
+            .and_then(|_| fs::rename(&temporary, path))
+            .and_then(|_| File::open(directory).and_then(|d| d.sync_all()))
+            .and_then(|_| self.file.set_len(0))
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| WalError::Unwritable(e.to_string()))
+    }
+}
+
+// Factors are written as typed when known, so decimal mode replays exactly.
+pub fn format_record(request: &PriceUpdateRequest) -> String {
+    let factor = |exact: Option<Decimal>, f: f64| exact.map_or(f.to_string(), |e| e.to_string());
+    format!(
+        "{} {} {} {} {} {}",
+        request.timestamp.to_rfc3339(),
+        request.exchange,
+        request.source_currency,
+        request.destination_currency,
+        factor(request.exact_forward_factor, request.forward_factor),
+        factor(request.exact_backward_factor, request.backward_factor)
+    )
+}
+
+fn parse_records(text: &str) -> Result<Vec<Record>, WalError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            if line.starts_with(':') {
+                return Ok(Record::Command(line.to_owned()));
+            }
+            let args: Vec<&str> = line.split_whitespace().collect();
+            validate_price_update_input(&args)
+                .map(Record::PriceUpdate)
+                .map_err(|e| WalError::InvalidRecord(i + 1, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KRAKEN_PRICE: &[&str] = &[
+        "2017-11-01T09:42:23+00:00",
+        "KRAKEN",
+        "BTC",
+        "USD",
+        "1000.10",
+        "0.0009",
+    ];
+
+    #[test]
+    fn should_replay_appended_updates_and_drop_a_cut_record() {
+        let path = std::env::temp_dir().join(format!("wal-{}.log", std::process::id()));
+        let path_arg = path.to_str().unwrap();
+        let _ = fs::remove_file(&path);
+
+        let update = validate_price_update_input(KRAKEN_PRICE).unwrap();
+        let (mut wal, records) = WriteAheadLog::open(path_arg).unwrap();
+        assert!(records.is_empty());
+        wal.append(&update).unwrap();
+        wal.append(&update).unwrap();
+        drop(wal);
+
+        // a crash in the middle of the third record
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str("2017-11-01T09:43:00+00:00 KRAKEN BT");
+        fs::write(&path, &content).unwrap();
+        let (mut wal, records) = WriteAheadLog::open(path_arg).unwrap();
+        let logged = Record::PriceUpdate(update.clone());
+        assert_eq!(vec![logged.clone(), logged.clone()], records);
+        // exact factors survive as typed
+        assert_eq!(
+            "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.10 0.0009",
+            format_record(&update)
+        );
+
+        // the cut record is gone, so new ones don't run into it
+        wal.append_command(":halt KRAKEN BTC USD").unwrap();
+        drop(wal);
+        let (_, records) = WriteAheadLog::open(path_arg).unwrap();
+        assert_eq!(
+            vec![
+                logged.clone(),
+                logged,
+                Record::Command(":halt KRAKEN BTC USD".to_owned())
+            ],
+            records
+        );
+
+        fs::write(&path, "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000 0\n").unwrap();
+        assert_eq!(
+            WalError::InvalidRecord(1, PriceUpdateRequestValidationError::InvalidBackwardfactor),
+            WriteAheadLog::open(path_arg).err().unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_empty_the_log_once_the_snapshot_is_written() {
+        let directory = std::env::temp_dir();
+        let wal_path = directory.join(format!("wal-compact-{}.log", std::process::id()));
+        let snapshot_path = directory.join(format!("wal-compact-{}.txt", std::process::id()));
+        let snapshot_arg = snapshot_path.to_str().unwrap();
+
+        let mut g = crate::graph::Graph::new();
+        let update = validate_price_update_input(KRAKEN_PRICE).unwrap();
+        let (mut wal, _) = WriteAheadLog::open(wal_path.to_str().unwrap()).unwrap();
+        wal.append(&update).unwrap();
+        g.update(&update);
+        wal.compact_into(snapshot_arg, &g.to_snapshot()).unwrap();
+
+        assert_eq!(0, fs::metadata(&wal_path).unwrap().len());
+        assert!(!std::path::Path::new(&format!("{}.tmp", snapshot_arg)).exists());
+        assert_eq!(
+            g.to_snapshot().to_text(),
+            fs::read_to_string(&snapshot_path).unwrap()
+        );
+        fs::remove_file(&wal_path).unwrap();
+        fs::remove_file(&snapshot_path).unwrap();
+    }
+}